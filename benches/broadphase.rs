@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use ludumdare44::broadphase::*;
+
+const ARENA: f32 = 1024.0;
+const ITERATIONS: u32 = 50;
+
+fn random_boxes(count: usize) -> Vec<Bounds> {
+    let mut rng = StdRng::seed_from_u64(44);
+    (0..count).map(|_| {
+        let x = rng.gen::<f32>() * ARENA;
+        let y = rng.gen::<f32>() * ARENA;
+        let width = 8.0 + rng.gen::<f32>() * 24.0;
+        let height = 8.0 + rng.gen::<f32>() * 24.0;
+        (x, y, x + width, y + height)
+    }).collect()
+}
+
+fn naive(boxes: &[Bounds]) -> usize {
+    let mut pairs = 0;
+    for (a, box_a) in boxes.iter().enumerate() {
+        for (b, box_b) in boxes.iter().enumerate() {
+            if a != b && overlaps(*box_a, *box_b) {
+                pairs += 1;
+            }
+        }
+    }
+    pairs
+}
+
+fn hashed(grid: &mut SpatialHash<usize>, boxes: &[Bounds]) -> usize {
+    grid.clear();
+    for (index, bounds) in boxes.iter().enumerate() {
+        grid.insert(index, *bounds);
+    }
+    let mut pairs = 0;
+    let mut found = Vec::new();
+    for (a, bounds) in boxes.iter().enumerate() {
+        found.clear();
+        grid.query(*bounds, &mut found);
+        pairs += found.iter().filter(|b| **b != a).count();
+    }
+    pairs
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut pairs = 0;
+    for _ in 0..ITERATIONS {
+        pairs = f();
+    }
+    (start.elapsed() / ITERATIONS, pairs)
+}
+
+fn main() {
+    for count in [100, 500, 1000, 2000].iter() {
+        let boxes = random_boxes(*count);
+        let mut grid = SpatialHash::new(32.0);
+        let (naive_time, naive_pairs) = time(|| naive(&boxes));
+        let (hashed_time, hashed_pairs) = time(|| hashed(&mut grid, &boxes));
+        assert_eq!(naive_pairs, hashed_pairs);
+        println!("{:>5} boxes: naive {:>10?} hashed {:>10?} ({} pairs)", count, naive_time, hashed_time, naive_pairs);
+    }
+}
@@ -0,0 +1,15 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.2" tiledversion="1.2.3" name="dungeon" tilewidth="16" tileheight="16" tilecount="4096" columns="64">
+ <image source="../Sprites.png" width="1024" height="1024"/>
+ <tile id="1024">
+  <properties>
+   <property name="sprite" type="int" value="28"/>
+  </properties>
+ </tile>
+ <tile id="1025">
+  <properties>
+   <property name="solid" type="bool" value="true"/>
+   <property name="sprite" type="int" value="29"/>
+  </properties>
+ </tile>
+</tileset>
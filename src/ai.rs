@@ -0,0 +1,443 @@
+use amethyst::{
+    ecs::*,
+    core::transform::*,
+    assets::{Asset, Handle, ProcessingState, Error},
+};
+use rand::rngs::StdRng;
+use crate::basics::*;
+use crate::combat::Health;
+use crate::drops::Chest;
+use crate::enemies::*;
+use crate::broadphase::Bounds;
+use crate::pathfinding::*;
+use crate::physics::static_obstacles;
+use crate::world::TileMap;
+use crate::random::GameRng;
+use crate::simulation::FixedStep;
+use crate::utils::*;
+
+const INVESTIGATE_DURATION: f32 = 4.0;
+const SEARCH_DURATION: f32 = 3.0;
+const SEARCH_TURN: f32 = 0.75;
+const CHASE_DISTANCE: f32 = 12.0;
+const PATROL_DISTANCE: f32 = 4.0;
+
+const WANDERS: [(f32, f32); 4] = [
+    ( 1.0,  0.0),
+    (-1.0,  0.0),
+    ( 0.0,  1.0),
+    ( 0.0, -1.0),
+];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Behavior {
+    Selector(Vec<Behavior>),
+    Sequence(Vec<Behavior>),
+    Not(Box<Behavior>),
+    SeesPlayer,
+    RemembersPlayer,
+    InMelee,
+    Aligned,
+    HealthBelow(f32),
+    NearHome(f32),
+    Idle,
+    Wander,
+    Chase,
+    Investigate,
+    Melee,
+    Shoot,
+    KeepDistance(f32),
+    Flee,
+    Patrol(Vec<(f32, f32)>),
+    Guard(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BehaviorTree {
+    pub root: Behavior,
+}
+impl Default for BehaviorTree {
+    fn default() -> BehaviorTree {
+        BehaviorTree { root: Behavior::Idle }
+    }
+}
+pub type BehaviorHandle = Handle<BehaviorTree>;
+impl Asset for BehaviorTree {
+    const NAME: &'static str = "LudumDare44::BehaviorTree";
+    type Data = BehaviorTree;
+    type HandleStorage = VecStorage<BehaviorHandle>;
+}
+impl From<BehaviorTree> for Result<ProcessingState<BehaviorTree>, Error> {
+    fn from(tree: BehaviorTree) -> Result<ProcessingState<BehaviorTree>, Error> {
+        Ok(ProcessingState::Loaded(tree))
+    }
+}
+
+#[derive(Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Brain {
+    tree: BehaviorTree,
+    home: Option<(f32, f32)>,
+    patrol_index: usize,
+    path: Option<Path>,
+}
+impl Brain {
+    pub fn new(tree: BehaviorTree) -> Brain {
+        Brain {
+            tree,
+            home: None,
+            patrol_index: 0,
+            path: None,
+        }
+    }
+    pub fn set_tree(&mut self, tree: BehaviorTree) {
+        self.tree = tree;
+        self.path = None;
+    }
+}
+
+struct Context<'a, 'g> {
+    position: (f32, f32),
+    home: (f32, f32),
+    dt: f32,
+    patrol_index: &'a mut usize,
+    path: &'a mut Option<Path>,
+    pathing: bool,
+    grid: &'a mut Option<NavGrid<'g>>,
+    tile_map: &'g TileMap,
+    obstacles: &'a dyn Fn() -> Vec<Bounds>,
+    animation: &'a mut AnimationController,
+    enemy: &'a mut ChaseAndWanderEnemy,
+    velocity: &'a mut Velocity,
+    melee: Option<&'a MeleeEnemy>,
+    ranged: Option<&'a RangedEnemy>,
+    health: Option<&'a Health>,
+    rng: &'a mut StdRng,
+    facing: Option<Rotation>,
+}
+impl<'a, 'g> Context<'a, 'g> {
+    fn walk(&mut self, (vx, vy): (f32, f32)) {
+        self.animation.start(self.enemy.walking_animation.clone(), AnimationState::Walking);
+        self.velocity.vx = vx;
+        self.velocity.vy = vy;
+        self.facing = Some(Rotation::toward(vx, vy));
+    }
+    fn stop(&mut self) {
+        self.animation.start(self.enemy.idle_animation.clone(), AnimationState::Idle);
+        self.velocity.vx = 0.0;
+        self.velocity.vy = 0.0;
+    }
+    fn path_toward(&mut self, target: (f32, f32), speed: f32, arrive: f32) -> bool {
+        self.pathing = true;
+        if length(target.0 - self.position.0, target.1 - self.position.1) <= arrive {
+            self.stop();
+            return true;
+        }
+        let goal = TileMap::tile_at(target);
+        let position = self.position;
+        let replan = match *self.path {
+            Some(ref mut path) => if path.failed() {
+                path.retry -= self.dt;
+                path.retry <= 0.0
+            } else {
+                path.goal != goal || path.off_course(position)
+            },
+            None => true,
+        };
+        if replan {
+            let path = Path::plan(self.grid(), position, target);
+            *self.path = Some(path);
+        }
+        let waypoint = match self.path.as_mut().and_then(|path| {
+            path.retarget(target);
+            path.next_waypoint(position)
+        }) {
+            Some(waypoint) => waypoint,
+            None => {
+                self.stop();
+                return false;
+            },
+        };
+        let dir = normalize(waypoint.0 - position.0, waypoint.1 - position.1);
+        self.walk((dir.0 * speed, dir.1 * speed));
+        false
+    }
+    fn grid(&mut self) -> &NavGrid<'g> {
+        if self.grid.is_none() {
+            *self.grid = Some(NavGrid::new(self.tile_map, &(self.obstacles)()));
+        }
+        self.grid.as_ref().unwrap()
+    }
+    fn condition(&self, passed: bool) -> Status {
+        if passed {
+            Status::Success
+        } else {
+            Status::Failure
+        }
+    }
+}
+
+fn tick(behavior: &Behavior, ctx: &mut Context) -> Status {
+    match behavior {
+        Behavior::Selector(children) => {
+            for child in children.iter() {
+                let status = tick(child, ctx);
+                if status != Status::Failure {
+                    return status;
+                }
+            }
+            Status::Failure
+        },
+        Behavior::Sequence(children) => {
+            for child in children.iter() {
+                let status = tick(child, ctx);
+                if status != Status::Success {
+                    return status;
+                }
+            }
+            Status::Success
+        },
+        Behavior::Not(child) => {
+            match tick(child, ctx) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            }
+        },
+        Behavior::SeesPlayer => ctx.condition(ctx.enemy.is_tracking()),
+        Behavior::RemembersPlayer => ctx.condition(ctx.enemy.last_known_position().is_some()),
+        Behavior::InMelee => ctx.condition(ctx.melee.map(|melee| melee.in_melee).unwrap_or(false)),
+        Behavior::Aligned => ctx.condition(ctx.ranged.map(|ranged| ranged.aligned).unwrap_or(false)),
+        Behavior::HealthBelow(fraction) => {
+            let below = match ctx.health {
+                Some(health) if health.max > 0 => (health.left as f32 / health.max as f32) < *fraction,
+                _ => false,
+            };
+            ctx.condition(below)
+        },
+        Behavior::NearHome(radius) => {
+            ctx.condition(length(ctx.home.0 - ctx.position.0, ctx.home.1 - ctx.position.1) <= *radius)
+        },
+        Behavior::Idle => {
+            ctx.stop();
+            Status::Running
+        },
+        Behavior::Wander => {
+            ctx.enemy.wander_progress -= ctx.dt;
+            if let Some((wx, wy)) = ctx.enemy.wander_direction {
+                if ctx.enemy.wander_progress <= 0.0 {
+                    ctx.stop();
+                    ctx.enemy.wander_progress = random_between(ctx.rng, 2.0, 5.0);
+                    ctx.enemy.wander_direction = None;
+                } else {
+                    let speed = ctx.enemy.wander_speed;
+                    ctx.walk((wx * speed, wy * speed));
+                }
+            } else {
+                ctx.stop();
+                if ctx.enemy.wander_progress <= 0.0 {
+                    ctx.enemy.wander_progress = random_between(ctx.rng, 1.0, 3.0);
+                    ctx.enemy.wander_direction = WANDERS.choose(ctx.rng).cloned();
+                }
+            }
+            Status::Running
+        },
+        Behavior::Chase => {
+            match ctx.enemy.last_known_position() {
+                Some(target) if ctx.enemy.is_tracking() => {
+                    let speed = ctx.enemy.chase_speed;
+                    if ctx.path_toward(target, speed, CHASE_DISTANCE) {
+                        Status::Success
+                    } else {
+                        Status::Running
+                    }
+                },
+                _ => Status::Failure,
+            }
+        },
+        Behavior::Investigate => {
+            let target = match ctx.enemy.last_known_position() {
+                Some(target) if !ctx.enemy.is_tracking() => target,
+                _ => return Status::Failure,
+            };
+            let search_left = ctx.enemy.search_left.unwrap_or(INVESTIGATE_DURATION + SEARCH_DURATION);
+            let next_left = search_left - ctx.dt;
+            if next_left <= 0.0 {
+                ctx.enemy.forget();
+                ctx.enemy.wander_progress = random_between(ctx.rng, 1.0, 3.0);
+                ctx.stop();
+                return Status::Success;
+            }
+            ctx.enemy.search_left = Some(next_left);
+            let speed = ctx.enemy.wander_speed;
+            if ctx.path_toward(target, speed, CHASE_DISTANCE) {
+                if (next_left / SEARCH_TURN).ceil() != (search_left / SEARCH_TURN).ceil() {
+                    if let Some((lx, ly)) = WANDERS.choose(ctx.rng).cloned() {
+                        ctx.facing = Some(Rotation::toward(lx, ly));
+                    }
+                }
+            }
+            Status::Running
+        },
+        Behavior::Melee => {
+            match ctx.melee {
+                Some(melee) if melee.in_melee => {
+                    if ctx.animation.start(melee.attack_animation.clone(), AnimationState::Attacking) {
+                        Status::Success
+                    } else {
+                        Status::Failure
+                    }
+                },
+                _ => Status::Failure,
+            }
+        },
+        Behavior::Shoot => {
+            match ctx.ranged {
+                Some(ranged) if ranged.aligned => {
+                    if ctx.animation.start(ranged.attack_animation.clone(), AnimationState::Attacking) {
+                        Status::Success
+                    } else {
+                        Status::Failure
+                    }
+                },
+                _ => Status::Failure,
+            }
+        },
+        Behavior::KeepDistance(distance) => {
+            let target = match ctx.enemy.last_known_position() {
+                Some(target) if ctx.enemy.is_tracking() => target,
+                _ => return Status::Failure,
+            };
+            let (dx, dy) = (target.0 - ctx.position.0, target.1 - ctx.position.1);
+            let speed = ctx.enemy.chase_speed;
+            if length(dx, dy) > *distance * 1.5 {
+                ctx.path_toward(target, speed, *distance);
+            } else if length(dx, dy) < *distance {
+                let (mut ax, ay) = normalize(-dx, -dy);
+                if ax == 0.0 && ay == 0.0 {
+                    ax = 1.0;
+                }
+                ctx.walk((ax * speed, ay * speed));
+            } else {
+                ctx.stop();
+                ctx.facing = Some(Rotation::toward(dx, dy));
+            }
+            Status::Running
+        },
+        Behavior::Flee => {
+            match ctx.enemy.last_known_position() {
+                Some((tx, ty)) => {
+                    let (mut dx, mut dy) = normalize(ctx.position.0 - tx, ctx.position.1 - ty);
+                    if dx == 0.0 && dy == 0.0 {
+                        dx = 1.0;
+                    }
+                    let speed = ctx.enemy.chase_speed;
+                    ctx.walk((dx * speed, dy * speed));
+                    Status::Running
+                },
+                None => Status::Failure,
+            }
+        },
+        Behavior::Patrol(points) => {
+            if points.is_empty() {
+                return Status::Failure;
+            }
+            let index = *ctx.patrol_index % points.len();
+            let target = (ctx.home.0 + points[index].0, ctx.home.1 + points[index].1);
+            let speed = ctx.enemy.wander_speed;
+            if ctx.path_toward(target, speed, PATROL_DISTANCE) {
+                *ctx.patrol_index = (index + 1) % points.len();
+            }
+            Status::Running
+        },
+        Behavior::Guard(radius) => {
+            let home = ctx.home;
+            let speed = ctx.enemy.wander_speed;
+            if length(home.0 - ctx.position.0, home.1 - ctx.position.1) > *radius {
+                ctx.path_toward(home, speed, PATROL_DISTANCE);
+            } else {
+                ctx.stop();
+            }
+            Status::Running
+        },
+    }
+}
+
+pub struct BehaviorSystem;
+impl<'s> System<'s> for BehaviorSystem {
+    type SystemData = (
+        WriteStorage<'s, Brain>,
+        WriteStorage<'s, AnimationController>,
+        WriteStorage<'s, ChaseAndWanderEnemy>,
+        ReadStorage<'s, MeleeEnemy>,
+        ReadStorage<'s, RangedEnemy>,
+        ReadStorage<'s, Health>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Physical>,
+        ReadStorage<'s, Chest>,
+        Read<'s, TileMap>,
+        WriteStorage<'s, Rotation>,
+        WriteStorage<'s, Velocity>,
+        Read<'s, FixedStep>,
+        WriteExpect<'s, GameRng>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (mut brains, mut animations, mut enemies, melee, ranged, health, transforms, physicals, chests, tile_map, mut rotations, mut velocities, time, mut game_rng, entities) : Self::SystemData) {
+        let rng = game_rng.stream("wander");
+        let obstacles = || {
+            let mut obstacles = static_obstacles(&transforms, &physicals);
+            for (_, transform, physical) in (&chests, &transforms, &physicals).join() {
+                if !physical.is_static {
+                    obstacles.push(physical.hitbox.bounds((transform.translation().x, transform.translation().y)));
+                }
+            }
+            obstacles
+        };
+        let mut grid = None;
+        for (brain, animation, enemy, transform, velocity, entity) in (&mut brains, &mut animations, &mut enemies, &transforms, &mut velocities, &entities).join() {
+            enemy.decay(time.delta_seconds());
+            if !animation.can_interrupt(AnimationState::Walking) {
+                continue;
+            }
+            let position = (transform.translation().x, transform.translation().y);
+            let Brain { tree, home, patrol_index, path } = brain;
+            let home = *home.get_or_insert(position);
+            let facing = {
+                let mut context = Context {
+                    position,
+                    home,
+                    dt: time.delta_seconds(),
+                    patrol_index,
+                    path,
+                    pathing: false,
+                    grid: &mut grid,
+                    tile_map: &tile_map,
+                    obstacles: &obstacles,
+                    animation,
+                    enemy,
+                    velocity,
+                    melee: melee.get(entity),
+                    ranged: ranged.get(entity),
+                    health: health.get(entity),
+                    rng: &mut *rng,
+                    facing: None,
+                };
+                tick(&tree.root, &mut context);
+                if !context.pathing {
+                    *context.path = None;
+                }
+                context.facing
+            };
+            if let Some(facing) = facing {
+                rotations.insert(entity, facing);
+            }
+        }
+    }
+}
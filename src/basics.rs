@@ -1,10 +1,17 @@
+use std::collections::{BTreeMap, VecDeque};
 use amethyst::{
     prelude::*,
     ecs::*,
     core::transform::*,
     renderer::*,
     audio::*,
+    core::{Time, ArcThreadPool},
+    assets::{Asset, AssetStorage, Handle, HotReloadStrategy, ProcessingState, Error},
+    shrev::{EventChannel, ReaderId},
 };
+use crate::broadphase::*;
+use crate::sounds::Sound;
+use crate::ai::BehaviorHandle;
 
 pub const stage: (f32, f32) = (200.0, 150.0);
 
@@ -14,6 +21,12 @@ pub struct MainAssets {
     pub player_hit_sound: SourceHandle,
     pub enemy_hit_sound: SourceHandle,
     pub purchase_sound: SourceHandle,
+    pub player_attack: AnimationHandle,
+    pub portal_spin: AnimationHandle,
+    pub heart_spin: AnimationHandle,
+    pub spend_heart_spin: AnimationHandle,
+    pub animations: BTreeMap<String, AnimationHandle>,
+    pub behaviors: BTreeMap<String, BehaviorHandle>,
 }
 
 #[derive(Component, Debug)]
@@ -25,35 +38,177 @@ pub struct Velocity {
 
 pub type HitboxCollision = (f32, f32, f32, f32);
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HitboxShape {
+    Rectangle,
+    Circle,
+    Oriented(f32),
+}
+impl Default for HitboxShape {
+    fn default() -> HitboxShape {
+        HitboxShape::Rectangle
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Hitbox {
     pub width: f32,
     pub height: f32,
     pub offset: (f32, f32),
+    pub shape: HitboxShape,
     pub debug_color: Option<Rgba>,
 }
 
+fn project(corners: &[(f32, f32); 4], (ax, ay): (f32, f32)) -> (f32, f32) {
+    let mut min = std::f32::MAX;
+    let mut max = std::f32::MIN;
+    for (x, y) in corners.iter() {
+        let projection = x * ax + y * ay;
+        min = min.min(projection);
+        max = max.max(projection);
+    }
+    (min, max)
+}
+
 impl Hitbox {
     pub fn new(size: f32) -> Hitbox {
-        Hitbox { width: size * 2.0, height: size * 2.0, offset: (0.0, 0.0), debug_color: None }
+        Hitbox { width: size * 2.0, height: size * 2.0, offset: (0.0, 0.0), shape: HitboxShape::Rectangle, debug_color: None }
     }
     pub fn new_at(size: f32, offset: (f32, f32)) -> Hitbox {
-        Hitbox { width: size * 2.0, height: size * 2.0, offset, debug_color: None }
+        Hitbox { width: size * 2.0, height: size * 2.0, offset, shape: HitboxShape::Rectangle, debug_color: None }
     }
     pub fn new_at_rect(width: f32, height: f32, offset: (f32, f32)) -> Hitbox {
-        Hitbox { width, height, offset, debug_color: None }
+        Hitbox { width, height, offset, shape: HitboxShape::Rectangle, debug_color: None }
+    }
+    pub fn new_circle(radius: f32, offset: (f32, f32)) -> Hitbox {
+        Hitbox { width: radius * 2.0, height: radius * 2.0, offset, shape: HitboxShape::Circle, debug_color: None }
+    }
+    pub fn new_oriented(width: f32, height: f32, angle: f32, offset: (f32, f32)) -> Hitbox {
+        Hitbox { width, height, offset, shape: HitboxShape::Oriented(angle), debug_color: None }
+    }
+    pub fn new_shaped(width: f32, height: f32, shape: HitboxShape, offset: (f32, f32)) -> Hitbox {
+        Hitbox { width, height, offset, shape, debug_color: None }
+    }
+    pub fn radius(&self) -> f32 {
+        self.width / 2.0
+    }
+    pub fn angle(&self) -> f32 {
+        match self.shape {
+            HitboxShape::Oriented(angle) => angle,
+            _ => 0.0,
+        }
+    }
+    pub fn center(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (x + self.offset.0, y + self.offset.1)
+    }
+    pub fn corners(&self, position: (f32, f32)) -> [(f32, f32); 4] {
+        let (cx, cy) = self.center(position);
+        let (sin, cos) = self.angle().sin_cos();
+        let (hw, hh) = (self.width / 2.0, self.height / 2.0);
+        let corner = |lx: f32, ly: f32| (cx + lx * cos - ly * sin, cy + lx * sin + ly * cos);
+        [corner(-hw, -hh), corner(hw, -hh), corner(hw, hh), corner(-hw, hh)]
+    }
+    pub fn bounds(&self, position: (f32, f32)) -> Bounds {
+        let (cx, cy) = self.center(position);
+        let (sin, cos) = self.angle().sin_cos();
+        let hw = (self.width * cos.abs() + self.height * sin.abs()) / 2.0;
+        let hh = (self.width * sin.abs() + self.height * cos.abs()) / 2.0;
+        (cx - hw, cy - hh, cx + hw, cy + hh)
     }
     pub fn depth(&self, other: &Hitbox, (mx, my): (f32, f32), (ox, oy): (f32, f32)) -> Option<HitboxCollision> {
         let dx = (ox + other.offset.0) - (mx + self.offset.0);
         let dy = (oy + other.offset.1) - (my + self.offset.1);
-        let sw = (self.width + other.width) / 2.0;
-        let sh = (self.height + other.height) / 2.0;
-        if dx.abs() < sw && dy.abs() < sh {
-            Some((dx, dy, sw - dx.abs(), sh - dy.abs()))
+        if self.shape == HitboxShape::Rectangle && other.shape == HitboxShape::Rectangle {
+            let sw = (self.width + other.width) / 2.0;
+            let sh = (self.height + other.height) / 2.0;
+            if dx.abs() < sw && dy.abs() < sh {
+                Some((dx, dy, sw - dx.abs(), sh - dy.abs()))
+            } else {
+                None
+            }
+        } else if let Some(((nx, ny), depth)) = self.penetration(other, (mx, my), (ox, oy)) {
+            let axis_depth = |n: f32| if n.abs() > 0.0001 { depth / n.abs() } else { std::f32::MAX };
+            Some((dx, dy, axis_depth(nx), axis_depth(ny)))
         } else {
             None
         }
     }
+    fn penetration(&self, other: &Hitbox, my_pos: (f32, f32), other_pos: (f32, f32)) -> Option<((f32, f32), f32)> {
+        match (self.shape, other.shape) {
+            (HitboxShape::Circle, HitboxShape::Circle) => {
+                let (mx, my) = self.center(my_pos);
+                let (ox, oy) = other.center(other_pos);
+                let (dx, dy) = (ox - mx, oy - my);
+                let distance = (dx * dx + dy * dy).sqrt();
+                let radii = self.radius() + other.radius();
+                if distance >= radii {
+                    None
+                } else if distance == 0.0 {
+                    Some(((1.0, 0.0), radii))
+                } else {
+                    Some(((dx / distance, dy / distance), radii - distance))
+                }
+            },
+            (HitboxShape::Circle, _) => {
+                other.circle_penetration(other_pos, self.center(my_pos), self.radius())
+                    .map(|((nx, ny), depth)| ((-nx, -ny), depth))
+            },
+            (_, HitboxShape::Circle) => {
+                self.circle_penetration(my_pos, other.center(other_pos), other.radius())
+            },
+            _ => {
+                self.box_penetration(other, my_pos, other_pos)
+            },
+        }
+    }
+    fn circle_penetration(&self, position: (f32, f32), (px, py): (f32, f32), radius: f32) -> Option<((f32, f32), f32)> {
+        let (cx, cy) = self.center(position);
+        let (sin, cos) = self.angle().sin_cos();
+        let (rx, ry) = (px - cx, py - cy);
+        let (lx, ly) = (rx * cos + ry * sin, -rx * sin + ry * cos);
+        let (hw, hh) = (self.width / 2.0, self.height / 2.0);
+        let (local_normal, depth) = if lx.abs() <= hw && ly.abs() <= hh {
+            let (gap_x, gap_y) = (hw - lx.abs(), hh - ly.abs());
+            if gap_x < gap_y {
+                ((lx.signum(), 0.0), gap_x + radius)
+            } else {
+                ((0.0, ly.signum()), gap_y + radius)
+            }
+        } else {
+            let (nx, ny) = (lx - lx.max(-hw).min(hw), ly - ly.max(-hh).min(hh));
+            let distance = (nx * nx + ny * ny).sqrt();
+            if distance >= radius {
+                return None;
+            }
+            ((nx / distance, ny / distance), radius - distance)
+        };
+        let (nx, ny) = local_normal;
+        Some(((nx * cos - ny * sin, nx * sin + ny * cos), depth))
+    }
+    fn box_penetration(&self, other: &Hitbox, my_pos: (f32, f32), other_pos: (f32, f32)) -> Option<((f32, f32), f32)> {
+        let mine = self.corners(my_pos);
+        let theirs = other.corners(other_pos);
+        let (mx, my) = self.center(my_pos);
+        let (ox, oy) = other.center(other_pos);
+        let (sin_a, cos_a) = self.angle().sin_cos();
+        let (sin_b, cos_b) = other.angle().sin_cos();
+        let axes = [(cos_a, sin_a), (-sin_a, cos_a), (cos_b, sin_b), (-sin_b, cos_b)];
+        let mut best: Option<((f32, f32), f32)> = None;
+        for (ax, ay) in axes.iter() {
+            let (min_a, max_a) = project(&mine, (*ax, *ay));
+            let (min_b, max_b) = project(&theirs, (*ax, *ay));
+            let overlap = max_a.min(max_b) - min_a.max(min_b);
+            if overlap <= 0.0 {
+                return None;
+            }
+            if best.map(|(_, depth)| overlap < depth).unwrap_or(true) {
+                let toward = (ox - mx) * ax + (oy - my) * ay;
+                let normal = if toward < 0.0 { (-ax, -ay) } else { (*ax, *ay) };
+                best = Some((normal, overlap));
+            }
+        }
+        best
+    }
 }
 
 #[derive(Component, Debug)]
@@ -70,83 +225,167 @@ impl Physical {
     pub fn new_static(size: f32) -> Physical {
         Physical { hitbox: Hitbox::new(size), is_static: true }
     }
-    pub fn new_wall(width: f32, height: f32) -> Physical {
-        Physical { hitbox: Hitbox::new_at_rect(width, height, (0.0, 0.0)), is_static: true }
-    }
     pub fn depth(&self, other: &Physical, my_pos: (f32, f32), other_pos: (f32, f32)) -> Option<HitboxCollision> {
         self.hitbox.depth(&other.hitbox, my_pos, other_pos)
     }
 }
 
-pub const HITSTATE_SIZE: usize = 16;
+pub type Layer = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Interaction {
+    PlayerDamage,
+    EnemyDamage,
+    Sight,
+    Aiming,
+    Purchase,
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerPair {
+    pub source: Layer,
+    pub target: Layer,
+    pub interaction: Interaction,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LayerPairConfig {
+    pub source: String,
+    pub target: String,
+    pub interaction: Interaction,
+}
 
-pub const ENEMY_ATTACK_BOX: usize = 0;
-pub const ENEMY_HITTABLE_BOX: usize = 1;
-pub const PLAYER_ATTACK_BOX: usize = 2;
-pub const PLAYER_HITTABLE_BOX: usize = 3;
-pub const ENEMY_SIGHT_BOX: usize = 4;
-pub const ENEMY_AIMING_BOX: usize = 5;
-pub const PORTAL_BOX: usize = 6;
-pub const PLAYER_INTERACT_BOX: usize = 7;
-pub const CHEST_BOX: usize = 8;
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollisionLayerConfig {
+    #[serde(default)]
+    pub layers: Vec<String>,
+    #[serde(default)]
+    pub pairs: Vec<LayerPairConfig>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CollisionLayers {
+    names: Vec<String>,
+    pairs: Vec<LayerPair>,
+}
+impl CollisionLayers {
+    pub fn from_config(config: &CollisionLayerConfig) -> Result<CollisionLayers, String> {
+        let mut layers = CollisionLayers::default();
+        for name in config.layers.iter() {
+            if layers.layer(name).is_some() {
+                return Err(format!("Collision layer {} is declared twice", name));
+            }
+            layers.names.push(name.to_string());
+        }
+        for pair in config.pairs.iter() {
+            let source = layers.layer(&pair.source)
+                .ok_or_else(|| format!("Collision pair uses undeclared layer {}", pair.source))?;
+            let target = layers.layer(&pair.target)
+                .ok_or_else(|| format!("Collision pair uses undeclared layer {}", pair.target))?;
+            layers.pair(source, target, pair.interaction);
+        }
+        Ok(layers)
+    }
+    pub fn layer(&self, name: &str) -> Option<Layer> {
+        self.names.iter().position(|layer_name| layer_name == name)
+    }
+    pub fn named(&self, name: &str) -> Layer {
+        self.layer(name).unwrap_or_else(|| panic!("No collision layer named {}", name))
+    }
+    pub fn name(&self, layer: Layer) -> Option<&str> {
+        self.names.get(layer).map(|name| name.as_str())
+    }
+    pub fn pair(&mut self, source: Layer, target: Layer, interaction: Interaction) {
+        let pair = LayerPair { source, target, interaction };
+        if !self.pairs.contains(&pair) {
+            self.pairs.push(pair);
+        }
+    }
+    pub fn pairs(&self) -> &[LayerPair] {
+        &self.pairs
+    }
+}
 
 #[derive(Component, Debug, Clone)]
 #[storage(HashMapStorage)]
 pub struct HitState {
-    hitboxes: [Option<Hitbox>; HITSTATE_SIZE],
+    hitboxes: Vec<Option<Hitbox>>,
+    animated: Vec<Layer>,
     rotation: Rotation,
 }
 
 impl HitState {
     pub fn new() -> HitState {
         HitState {
-            hitboxes: Default::default(),
-            rotation: Rotation::East,
+            hitboxes: Vec::new(),
+            animated: Vec::new(),
+            rotation: Rotation::EAST,
         }
     }
-    pub fn set(&mut self, index: usize, width: f32, height: f32, offset: (f32, f32)) {
-        self.hitboxes[index] = Some(Hitbox::new_at_rect(width, height, offset));
+    pub fn set(&mut self, index: Layer, width: f32, height: f32, offset: (f32, f32)) {
+        self.set_hitbox(index, Hitbox::new_at_rect(width, height, offset));
+    }
+    pub fn clear(&mut self, index: Layer) {
+        if index < self.hitboxes.len() {
+            self.hitboxes[index] = None;
+        }
     }
-    pub fn clear(&mut self, index: usize) {
-        self.hitboxes[index] = None;
+    pub fn set_hitbox(&mut self, index: Layer, hitbox: Hitbox) {
+        if index >= self.hitboxes.len() {
+            self.hitboxes.resize(index + 1, None);
+        }
+        self.hitboxes[index] = Some(hitbox);
+    }
+    pub fn set_animated(&mut self, index: Layer, hitbox: Hitbox) {
+        self.set_hitbox(index, hitbox);
+        if !self.animated.contains(&index) {
+            self.animated.push(index);
+        }
+    }
+    pub fn clear_animated(&mut self) {
+        for index in self.animated.drain(..) {
+            if index < self.hitboxes.len() {
+                self.hitboxes[index] = None;
+            }
+        }
     }
     pub fn rotate(&mut self, rotation: Rotation) {
         self.rotation = rotation;
     }
-    pub fn get_all(&self) -> [Option<Hitbox>; HITSTATE_SIZE] {
-        let mut rotated = self.hitboxes.clone();
-        for i in 0..rotated.len() {
-            if let Some(mut hitbox) = rotated[i].as_mut() {
-                hitbox.offset = self.rotation.rotate(hitbox.offset);
-                let (width, height) = match self.rotation {
-                    Rotation::North | Rotation::South => {
-                        (hitbox.height, hitbox.width)
+    fn rotated(&self, hitbox: &Hitbox) -> Hitbox {
+        let mut rotated = hitbox.clone();
+        rotated.offset = self.rotation.rotate(hitbox.offset);
+        match hitbox.shape {
+            HitboxShape::Rectangle => {
+                match self.rotation.quarter_turns() {
+                    Some(1) | Some(3) => {
+                        rotated.width = hitbox.height;
+                        rotated.height = hitbox.width;
                     },
-                    Rotation::East | Rotation::West => {
-                        (hitbox.width, hitbox.height)
-                    }
-                };
-                hitbox.width = width;
-                hitbox.height = height;
-            }
+                    Some(_) => {
+                    },
+                    None => {
+                        rotated.shape = HitboxShape::Oriented(self.rotation.angle());
+                    },
+                }
+            },
+            HitboxShape::Circle => {
+            },
+            HitboxShape::Oriented(angle) => {
+                rotated.shape = HitboxShape::Oriented(angle + self.rotation.angle());
+            },
         }
         rotated
     }
-    pub fn get(&self, index: usize) -> Option<Hitbox> {
-        if let Some(hitbox) = self.hitboxes[index] {
-            let mut rotated = hitbox.clone();
-            rotated.offset = self.rotation.rotate(hitbox.offset);
-            let (width, height) = match self.rotation {
-                Rotation::North | Rotation::South => {
-                    (rotated.height, rotated.width)
-                },
-                Rotation::East | Rotation::West => {
-                    (rotated.width, rotated.height)
-                }
-            };
-            rotated.width = width;
-            rotated.height = height;
-            Some(rotated)
+    pub fn get_all(&self) -> Vec<Option<Hitbox>> {
+        self.hitboxes.iter()
+            .map(|hitbox| hitbox.as_ref().map(|hitbox| self.rotated(hitbox)))
+            .collect()
+    }
+    pub fn get(&self, index: Layer) -> Option<Hitbox> {
+        if let Some(Some(hitbox)) = self.hitboxes.get(index) {
+            Some(self.rotated(hitbox))
         } else {
             None
         }
@@ -159,36 +398,103 @@ pub enum AnimationState {
     Attacking,
     Staggered,
 }
-#[derive(Component, Debug, Clone, Copy)]
+impl AnimationState {
+    pub fn priority(&self) -> i32 {
+        match self {
+            AnimationState::Idle => 0,
+            AnimationState::Walking => 0,
+            AnimationState::Attacking => 1,
+            AnimationState::Staggered => 2,
+        }
+    }
+}
+const QUARTER_TURN: f32 = std::f32::consts::PI / 2.0;
+const FULL_TURN: f32 = std::f32::consts::PI * 2.0;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
 #[storage(VecStorage)]
-pub enum Rotation {
-    East,
-    South,
-    West,
-    North,
+pub struct Rotation {
+    angle: f32,
 }
 impl Rotation {
+    pub const EAST: Rotation = Rotation { angle: 0.0 };
+    pub const NORTH: Rotation = Rotation { angle: QUARTER_TURN };
+    pub const WEST: Rotation = Rotation { angle: QUARTER_TURN * 2.0 };
+    pub const SOUTH: Rotation = Rotation { angle: QUARTER_TURN * 3.0 };
+    pub fn from_angle(angle: f32) -> Rotation {
+        let angle = angle % FULL_TURN;
+        Rotation { angle: if angle < 0.0 { angle + FULL_TURN } else { angle } }
+    }
+    pub fn toward(x: f32, y: f32) -> Rotation {
+        Rotation::from_angle(y.atan2(x))
+    }
+    pub fn snapped(&self, directions: u32) -> Rotation {
+        let step = FULL_TURN / directions as f32;
+        Rotation::from_angle((self.angle / step).round() * step)
+    }
+    fn quarter_turns(&self) -> Option<i32> {
+        let turns = self.angle / QUARTER_TURN;
+        if (turns - turns.round()).abs() < 0.0001 {
+            Some(turns.round() as i32 % 4)
+        } else {
+            None
+        }
+    }
+    pub fn is_axis_aligned(&self) -> bool {
+        self.quarter_turns().is_some()
+    }
     pub fn rotate(&self, (x, y): (f32, f32)) -> (f32, f32) {
-        match self {
-            Rotation::East => (x, y),
-            Rotation::South => (y, -x),
-            Rotation::West => (-x, -y),
-            Rotation::North => (-y, x),
+        match self.quarter_turns() {
+            Some(0) => (x, y),
+            Some(1) => (-y, x),
+            Some(2) => (-x, -y),
+            Some(3) => (y, -x),
+            _ => {
+                let (sin, cos) = self.angle.sin_cos();
+                (x * cos - y * sin, x * sin + y * cos)
+            },
         }
     }
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
     pub fn rotate_euler(&self, transform: &mut Transform) {
-        use std::f32;
-        transform.set_rotation_euler(0.0, 0.0, match self {
-            Rotation::East => 0.0,
-            Rotation::South => f32::consts::PI * 3.0 / 2.0,
-            Rotation::West => f32::consts::PI,
-            Rotation::North => f32::consts::PI / 2.0,
-        });
+        transform.set_rotation_euler(0.0, 0.0, self.angle());
     }
 }
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FrameEvent {
+    Sound(Sound),
+    Spawn {
+        name: String,
+        offset: (f32, f32),
+    },
+    Invulnerable(f32),
+    ScreenShake {
+        magnitude: f32,
+        duration: f32,
+    },
+}
+#[derive(Debug, Clone)]
+pub struct AnimationEvent {
+    pub entity: Entity,
+    pub event: FrameEvent,
+}
+#[derive(Debug, Default)]
+pub struct ScreenShake {
+    pub magnitude: f32,
+    pub time_left: f32,
+}
+impl ScreenShake {
+    pub fn shake(&mut self, magnitude: f32, duration: f32) {
+        self.magnitude = self.magnitude.max(magnitude);
+        self.time_left = self.time_left.max(duration);
+    }
+}
+#[derive(Debug, Clone)]
 pub struct AnimationFrame {
-    pub hitboxes: [Option<Hitbox>; HITSTATE_SIZE],
+    pub hitboxes: Vec<(Layer, Hitbox)>,
+    pub events: Vec<FrameEvent>,
     pub velocity: Option<(f32, f32)>,
     pub sprite: Option<usize>,
     pub duration: f32,
@@ -196,14 +502,25 @@ pub struct AnimationFrame {
 #[derive(Debug, Clone)]
 pub struct HitboxAnimation {
     frames: Vec<AnimationFrame>,
+    cancel_windows: Vec<(usize, usize)>,
 }
 impl HitboxAnimation {
     pub fn new() -> HitboxAnimation {
-        HitboxAnimation { frames: Vec::new() }
+        HitboxAnimation {
+            frames: Vec::new(),
+            cancel_windows: Vec::new(),
+        }
+    }
+    pub fn add_cancel_window(&mut self, first: usize, last: usize) {
+        self.cancel_windows.push((first, last));
+    }
+    fn can_cancel_at(&self, index: usize) -> bool {
+        self.cancel_windows.iter().any(|&(first, last)| first <= index && index <= last)
     }
     pub fn add_frame(&mut self, duration: f32) -> usize {
         self.frames.push(AnimationFrame {
-            hitboxes: Default::default(),
+            hitboxes: Vec::new(),
+            events: Vec::new(),
             velocity: None,
             sprite: None,
             duration,
@@ -212,25 +529,31 @@ impl HitboxAnimation {
     }
     pub fn add_frame_with_velocity(&mut self, velocity: (f32, f32), duration: f32) -> usize {
         self.frames.push(AnimationFrame {
-            hitboxes: Default::default(),
+            hitboxes: Vec::new(),
+            events: Vec::new(),
             velocity: Some(velocity),
             sprite: None,
             duration,
         });
         self.frames.len() - 1
     }
-    pub fn set_hitbox(&mut self, index: usize, hitbox_index: usize, hitbox: Hitbox) {
-        self.frames[index].hitboxes[hitbox_index] = Some(hitbox);
+    pub fn set_hitbox(&mut self, index: usize, layer: Layer, hitbox: Hitbox) {
+        let hitboxes = &mut self.frames[index].hitboxes;
+        hitboxes.retain(|(existing, _)| *existing != layer);
+        hitboxes.push((layer, hitbox));
     }
     pub fn set_sprite(&mut self, index: usize, sprite: usize) {
         self.frames[index].sprite = Some(sprite);
     }
-    fn get_frame_at(&self, progress: f32) -> Option<AnimationFrame> {
+    pub fn add_event(&mut self, index: usize, event: FrameEvent) {
+        self.frames[index].events.push(event);
+    }
+    fn get_frame_index_at(&self, progress: f32) -> Option<usize> {
         let mut progress_left = progress;
         let mut found_frame = None;
-        for frame in self.frames.iter() {
+        for (index, frame) in self.frames.iter().enumerate() {
             if progress_left < frame.duration {
-                found_frame = Some(*frame);
+                found_frame = Some(index);
                 break;
             }
             progress_left -= frame.duration;
@@ -238,33 +561,234 @@ impl HitboxAnimation {
         found_frame
     }
 }
-pub trait HitboxCollisionSystem<'s>: System<'s> {
-    type ExtraData: SystemData<'s>;
-    fn source() -> usize;
-    fn target() -> usize;
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData);
-    fn check_collisions(&mut self,
-        (hitboxes, transforms, entities, mut extra) : (ReadStorage<'s, HitState>, WriteStorage<'s, Transform>, Entities<'s>, Self::ExtraData)
-    ) {
-        for (hitbox_a, transform_a, entity_a) in (&hitboxes, &transforms, &entities).join() {
-            if hitbox_a.get(Self::source()).is_none() {
-                continue;
+pub type AnimationHandle = Handle<HitboxAnimation>;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameHitboxData {
+    pub layer: String,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub offset: (f32, f32),
+    #[serde(default)]
+    pub shape: HitboxShape,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationFrameData {
+    pub duration: f32,
+    #[serde(default)]
+    pub velocity: Option<(f32, f32)>,
+    #[serde(default)]
+    pub sprite: Option<usize>,
+    #[serde(default)]
+    pub hitboxes: Vec<FrameHitboxData>,
+    #[serde(default)]
+    pub events: Vec<FrameEvent>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitboxAnimationData {
+    pub frames: Vec<AnimationFrameData>,
+    #[serde(default)]
+    pub cancel: Vec<(usize, usize)>,
+}
+impl HitboxAnimationData {
+    pub fn build(&self, layers: &CollisionLayers) -> Result<HitboxAnimation, Error> {
+        let mut animation = HitboxAnimation::new();
+        for frame_data in self.frames.iter() {
+            let frame = match frame_data.velocity {
+                Some(velocity) => animation.add_frame_with_velocity(velocity, frame_data.duration),
+                None => animation.add_frame(frame_data.duration),
+            };
+            if let Some(sprite) = frame_data.sprite {
+                animation.set_sprite(frame, sprite);
             }
-            for (hitbox_b, transform_b, entity_b) in (&hitboxes, &transforms, &entities).join() {
-                if entity_a.id() == entity_b.id() {
-                } else if hitbox_b.get(Self::target()).is_none() {
-                } else if let (Some(attack), Some(hit)) =
-                    (hitbox_a.get(Self::source()), hitbox_b.get(Self::target())) {
-                    let mx = transform_a.translation().x;
-                    let my = transform_a.translation().y;
-                    let ox = transform_b.translation().x;
-                    let oy = transform_b.translation().y;
-                    if let Some(collision) = attack.depth(&hit, (mx, my), (ox, oy)) {
-                        self.collide(collision, entity_a, entity_b, &transforms, &mut extra);
+            for event in frame_data.events.iter() {
+                animation.add_event(frame, event.clone());
+            }
+            for hitbox in frame_data.hitboxes.iter() {
+                if hitbox.width <= 0.0 || hitbox.height <= 0.0 {
+                    return Err(format!("Hitbox on layer {} has no area in frame {}", hitbox.layer, frame).into());
+                }
+                if hitbox.shape == HitboxShape::Circle && hitbox.width != hitbox.height {
+                    return Err(format!("Circle hitbox on layer {} must have equal width and height in frame {}", hitbox.layer, frame).into());
+                }
+                let layer = match layers.layer(&hitbox.layer) {
+                    Some(layer) => layer,
+                    None => return Err(format!("Unknown hitbox layer {} in frame {}", hitbox.layer, frame).into()),
+                };
+                animation.set_hitbox(frame, layer, Hitbox::new_shaped(hitbox.width, hitbox.height, hitbox.shape, hitbox.offset));
+            }
+        }
+        for &(first, last) in self.cancel.iter() {
+            if first > last || last >= self.frames.len() {
+                return Err(format!("Cancel window {}..{} is outside of the animation's {} frames", first, last, self.frames.len()).into());
+            }
+            animation.add_cancel_window(first, last);
+        }
+        Ok(animation)
+    }
+}
+impl Asset for HitboxAnimation {
+    const NAME: &'static str = "LudumDare44::HitboxAnimation";
+    type Data = HitboxAnimationData;
+    type HandleStorage = VecStorage<AnimationHandle>;
+}
+pub struct AnimationProcessor;
+impl<'s> System<'s> for AnimationProcessor {
+    type SystemData = (
+        Write<'s, AssetStorage<HitboxAnimation>>,
+        Read<'s, CollisionLayers>,
+        ReadExpect<'s, ArcThreadPool>,
+        Read<'s, Time>,
+        Option<Read<'s, HotReloadStrategy>>,
+    );
+    fn run(&mut self, (mut animations, layers, pool, time, strategy) : Self::SystemData) {
+        animations.process(
+            |data| data.build(&layers).map(ProcessingState::Loaded),
+            time.frame_number(),
+            &**pool,
+            strategy.as_ref().map(|strategy| &**strategy),
+        );
+    }
+}
+pub struct HitboxGrid {
+    hash: SpatialHash<Entity>,
+}
+impl Default for HitboxGrid {
+    fn default() -> HitboxGrid {
+        HitboxGrid { hash: SpatialHash::new(32.0) }
+    }
+}
+impl HitboxGrid {
+    pub fn query(&self, bounds: Bounds) -> Vec<Entity> {
+        let mut found = Vec::new();
+        self.hash.query(bounds, &mut found);
+        found
+    }
+}
+pub struct BroadphaseSystem;
+impl<'s> System<'s> for BroadphaseSystem {
+    type SystemData = (
+        Write<'s, HitboxGrid>,
+        ReadStorage<'s, HitState>,
+        ReadStorage<'s, Transform>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (mut grid, hitboxes, transforms, entities) : Self::SystemData) {
+        grid.hash.clear();
+        for (hitstate, transform, entity) in (&hitboxes, &transforms, &entities).join() {
+            let position = (transform.translation().x, transform.translation().y);
+            let mut total: Option<Bounds> = None;
+            for m_hitbox in hitstate.get_all().iter() {
+                if let Some(hitbox) = m_hitbox {
+                    let (x0, y0, x1, y1) = hitbox.bounds(position);
+                    total = Some(match total {
+                        Some((tx0, ty0, tx1, ty1)) => (tx0.min(x0), ty0.min(y0), tx1.max(x1), ty1.max(y1)),
+                        None => (x0, y0, x1, y1),
+                    });
+                }
+            }
+            if let Some(bounds) = total {
+                grid.hash.insert(entity, bounds);
+            }
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContactPhase {
+    Enter,
+    Stay,
+    Exit,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct ContactEvent {
+    pub source: Layer,
+    pub target: Layer,
+    pub interaction: Interaction,
+    pub phase: ContactPhase,
+    pub entity_a: Entity,
+    pub entity_b: Entity,
+    pub collision: HitboxCollision,
+}
+type ContactKey = (Layer, Layer, Interaction, Entity, Entity);
+pub struct CollisionSystem {
+    contacts: BTreeMap<ContactKey, HitboxCollision>,
+}
+impl CollisionSystem {
+    pub fn new() -> CollisionSystem {
+        CollisionSystem { contacts: BTreeMap::new() }
+    }
+}
+impl<'s> System<'s> for CollisionSystem {
+    type SystemData = (
+        ReadStorage<'s, HitState>,
+        ReadStorage<'s, Transform>,
+        Entities<'s>,
+        Read<'s, HitboxGrid>,
+        Read<'s, CollisionLayers>,
+        Write<'s, EventChannel<ContactEvent>>,
+    );
+    fn run(&mut self, (hitboxes, transforms, entities, grid, layers, mut events) : Self::SystemData) {
+        let mut current = BTreeMap::new();
+        for (hitbox_a, transform_a, entity_a) in (&hitboxes, &transforms, &entities).join() {
+            let mx = transform_a.translation().x;
+            let my = transform_a.translation().y;
+            for LayerPair { source, target, interaction } in layers.pairs().iter() {
+                if let Some(attack) = hitbox_a.get(*source) {
+                    for entity_b in grid.query(attack.bounds((mx, my))) {
+                        if entity_a.id() == entity_b.id() {
+                        } else if let (Some(hitbox_b), Some(transform_b)) = (hitboxes.get(entity_b), transforms.get(entity_b)) {
+                            if let Some(hit) = hitbox_b.get(*target) {
+                                let ox = transform_b.translation().x;
+                                let oy = transform_b.translation().y;
+                                if let Some(collision) = attack.depth(&hit, (mx, my), (ox, oy)) {
+                                    current.insert((*source, *target, *interaction, entity_a, entity_b), collision);
+                                }
+                            }
+                        }
                     }
                 }
             }
         }
+        for (key, collision) in current.iter() {
+            let (source, target, interaction, entity_a, entity_b) = *key;
+            let phase = if self.contacts.contains_key(key) {
+                ContactPhase::Stay
+            } else {
+                ContactPhase::Enter
+            };
+            events.single_write(ContactEvent { source, target, interaction, phase, entity_a, entity_b, collision: *collision });
+        }
+        for (key, collision) in self.contacts.iter() {
+            if !current.contains_key(key) {
+                let (source, target, interaction, entity_a, entity_b) = *key;
+                events.single_write(ContactEvent { source, target, interaction, phase: ContactPhase::Exit, entity_a, entity_b, collision: *collision });
+            }
+        }
+        self.contacts = current;
+    }
+}
+pub trait HitboxCollisionSystem<'s>: System<'s> {
+    type ExtraData: SystemData<'s>;
+    fn interaction() -> Interaction;
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>>;
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData);
+    fn prepare(&mut self, _transforms: &ReadStorage<'s, Transform>, _extra: &Self::ExtraData) {}
+    fn setup_contacts(&mut self, res: &mut Resources) {
+        *self.contacts() = Some(res.fetch_mut::<EventChannel<ContactEvent>>().register_reader());
+    }
+    fn check_collisions(&mut self,
+        (events, transforms, mut extra) : (Read<'s, EventChannel<ContactEvent>>, ReadStorage<'s, Transform>, Self::ExtraData)
+    ) {
+        let contacts: Vec<ContactEvent> = events.read(self.contacts().as_mut().unwrap())
+            .filter(|contact| contact.interaction == Self::interaction())
+            .cloned()
+            .collect();
+        if !contacts.is_empty() {
+            self.prepare(&transforms, &extra);
+        }
+        for contact in contacts.iter() {
+            self.collide(contact.phase, contact.collision, contact.entity_a, contact.entity_b, &transforms, &mut extra);
+        }
     }
 }
 #[derive(Component, Debug)]
@@ -274,6 +798,9 @@ pub struct AnimationController {
     animation: Option<HitboxAnimation>,
     state: AnimationState,
     looping: bool,
+    frame_index: Option<usize>,
+    events: Vec<FrameEvent>,
+    queued: VecDeque<(HitboxAnimation, AnimationState)>,
 }
 impl AnimationController {
     pub fn new() -> AnimationController {
@@ -282,38 +809,104 @@ impl AnimationController {
             animation: None,
             state: AnimationState::Idle,
             looping: false,
+            frame_index: None,
+            events: Vec::new(),
+            queued: VecDeque::new(),
         }
     }
-    pub fn start(&mut self, animation: HitboxAnimation, state: AnimationState) {
+    fn play(&mut self, animation: HitboxAnimation, state: AnimationState) {
         self.animation = Some(animation);
         self.progress = 0.0;
         self.state = state;
+        self.looping = false;
+        self.frame_index = None;
+    }
+    pub fn in_cancel_window(&self) -> bool {
+        match (&self.animation, self.frame_index) {
+            (Some(animation), Some(index)) => animation.can_cancel_at(index),
+            _ => false,
+        }
+    }
+    pub fn can_interrupt(&self, state: AnimationState) -> bool {
+        !self.active() || state.priority() >= self.state.priority()
+    }
+    pub fn start(&mut self, animation: HitboxAnimation, state: AnimationState) -> bool {
+        let replace = !self.active()
+            || state.priority() > self.state.priority()
+            || (state.priority() == self.state.priority() && (state != self.state || self.in_cancel_window()));
+        if replace {
+            if state.priority() > self.state.priority() {
+                self.queued.clear();
+            }
+            self.play(animation, state);
+        }
+        replace
+    }
+    pub fn queue(&mut self, animation: HitboxAnimation, state: AnimationState) -> bool {
+        if self.start(animation.clone(), state) {
+            return true;
+        }
+        if state.priority() < self.state.priority() || self.queued.iter().any(|(_, queued)| *queued == state) {
+            return false;
+        }
+        self.queued.push_back((animation, state));
+        true
+    }
+    pub fn cancel(&mut self) {
+        self.animation = None;
+        self.state = AnimationState::Idle;
+        self.frame_index = None;
+        self.queued.clear();
     }
     pub fn start_loop(&mut self, animation: HitboxAnimation) {
         self.animation = Some(animation);
         self.progress = 0.0;
         self.looping = true;
+        self.frame_index = None;
     }
     pub fn step(&mut self, delta_seconds: f32) -> Option<AnimationFrame> {
+        if self.in_cancel_window() {
+            if let Some((animation, state)) = self.queued.pop_front() {
+                self.play(animation, state);
+                return self.step(0.0);
+            }
+        }
         self.progress += delta_seconds;
         if let Some(animation) = &self.animation {
-            let mut frame = animation.get_frame_at(self.progress);
-            if self.looping && frame.is_none() {
+            let mut index = animation.get_frame_index_at(self.progress);
+            if self.looping && index.is_none() {
+                if let Some(previous) = self.frame_index {
+                    for frame in animation.frames[(previous + 1)..].iter() {
+                        self.events.extend(frame.events.iter().cloned());
+                    }
+                }
+                self.frame_index = None;
                 self.progress = 0.0;
-                frame = animation.get_frame_at(self.progress);
+                index = animation.get_frame_index_at(self.progress);
             }
-            if let Some(frame) = frame {
-                Some(frame)
-            } else {
-                self.animation = None;
-                self.state = AnimationState::Idle;
-                None
+            if let Some(index) = index {
+                let first = match self.frame_index {
+                    Some(previous) if previous <= index => previous + 1,
+                    _ => 0,
+                };
+                for frame in animation.frames[first..=index].iter() {
+                    self.events.extend(frame.events.iter().cloned());
+                }
+                self.frame_index = Some(index);
+                return Some(animation.frames[index].clone());
             }
-        } else {
-            self.animation = None;
-            self.state = AnimationState::Idle;
-            None
         }
+        self.animation = None;
+        self.state = AnimationState::Idle;
+        self.frame_index = None;
+        if let Some((animation, state)) = self.queued.pop_front() {
+            self.play(animation, state);
+            return self.step(0.0);
+        }
+        None
+    }
+    pub fn take_events(&mut self) -> Vec<FrameEvent> {
+        std::mem::replace(&mut self.events, Vec::new())
     }
     pub fn active(&self) -> bool {
         !self.animation.is_none()
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+use amethyst::{
+    prelude::*,
+    ecs::*,
+    core::transform::*,
+    assets::AssetStorage,
+    renderer::SpriteRender,
+};
+use crate::ai::*;
+use crate::basics::*;
+use crate::combat::Health;
+use crate::enemies::*;
+use crate::sprites::*;
+use crate::ui::*;
+use crate::utils::*;
+
+pub const BOSS_PIPS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddData {
+    pub enemy: String,
+    pub offset: (f32, f32),
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BossPhaseData {
+    pub health: f32,
+    pub behavior: String,
+    #[serde(default)]
+    pub melee: Option<MeleeData>,
+    #[serde(default)]
+    pub ranged: Option<RangedData>,
+    #[serde(default)]
+    pub speeds: Option<(f32, f32)>,
+    #[serde(default)]
+    pub hitboxes: Vec<FrameHitboxData>,
+    #[serde(default)]
+    pub adds: Vec<AddData>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BossData {
+    pub archetype: String,
+    pub phases: Vec<BossPhaseData>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bosses {
+    #[serde(default)]
+    pub every: i32,
+    #[serde(default)]
+    pub order: Vec<String>,
+    #[serde(default)]
+    pub bosses: BTreeMap<String, BossData>,
+}
+impl Bosses {
+    pub fn for_level(&self, levels: i32) -> Option<&str> {
+        if self.every <= 0 || levels <= 0 || levels % self.every != 0 || self.order.is_empty() {
+            return None;
+        }
+        let index = (levels / self.every - 1) as usize % self.order.len();
+        Some(&self.order[index])
+    }
+    fn phases(&self) -> impl Iterator<Item = &BossPhaseData> {
+        self.bosses.values().flat_map(|boss| boss.phases.iter())
+    }
+    pub fn animation_names(&self) -> BTreeSet<String> {
+        let melee = self.phases()
+            .filter_map(|phase| phase.melee.as_ref().map(|melee| melee.animation.clone()));
+        let ranged = self.phases()
+            .filter_map(|phase| phase.ranged.as_ref().map(|ranged| ranged.animation.clone()));
+        melee.chain(ranged).collect()
+    }
+    pub fn behavior_names(&self) -> BTreeSet<String> {
+        self.phases().map(|phase| phase.behavior.clone()).collect()
+    }
+    pub fn validate(&self, archetypes: &EnemyArchetypes, layers: &CollisionLayers) -> Result<(), String> {
+        for name in self.order.iter() {
+            if !self.bosses.contains_key(name) {
+                return Err(format!("Boss order lists unknown boss {}", name));
+            }
+        }
+        for (name, boss) in self.bosses.iter() {
+            let owner = format!("Boss {}", name);
+            if archetypes.get(&boss.archetype).is_none() {
+                return Err(format!("{} uses unknown archetype {}", owner, boss.archetype));
+            }
+            for phase in boss.phases.iter() {
+                check_hitbox_layers(&owner, &phase.hitboxes, layers)?;
+                check_behavior(&owner, &phase.behavior)?;
+                if let Some(melee) = phase.melee.as_ref() {
+                    check_animation(&owner, &melee.animation)?;
+                }
+                if let Some(ranged) = phase.ranged.as_ref() {
+                    check_animation(&owner, &ranged.animation)?;
+                }
+                if let Some(add) = phase.adds.iter().find(|add| archetypes.get(&add.enemy).is_none()) {
+                    return Err(format!("{} summons unknown enemy {}", owner, add.enemy));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct Boss {
+    phases: Vec<BossPhaseData>,
+    phase: Option<usize>,
+}
+impl Boss {
+    pub fn new(phases: Vec<BossPhaseData>) -> Boss {
+        Boss { phases, phase: None }
+    }
+    pub fn phase(&self) -> Option<usize> {
+        self.phase
+    }
+}
+
+#[derive(Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct BossHealthPip {
+    index: usize,
+}
+
+pub fn spawn_boss(world: &mut World, name: &str, x: f32, y: f32) -> Option<Entity> {
+    let data = world.read_resource::<Bosses>().bosses.get(name).cloned()?;
+    let boss = spawn_enemy(world, &data.archetype, x, y)?
+        .with(Boss::new(data.phases))
+        .build();
+    for index in 0..BOSS_PIPS {
+        let offset = ((index as f32 - (BOSS_PIPS as f32 - 1.0) / 2.0) * 16.0, 0.0);
+        draw_sprite(world, FULL_HEART, Anchor::Bottom, offset)
+            .with(BossHealthPip { index })
+            .build();
+    }
+    Some(boss)
+}
+
+pub struct BossPhaseSystem;
+impl<'s> System<'s> for BossPhaseSystem {
+    type SystemData = (
+        WriteStorage<'s, Boss>,
+        ReadStorage<'s, Health>,
+        ReadStorage<'s, Transform>,
+        WriteStorage<'s, Brain>,
+        WriteStorage<'s, MeleeEnemy>,
+        WriteStorage<'s, RangedEnemy>,
+        WriteStorage<'s, ChaseAndWanderEnemy>,
+        WriteStorage<'s, HitState>,
+        Read<'s, CollisionLayers>,
+        ReadExpect<'s, MainAssets>,
+        Read<'s, AssetStorage<HitboxAnimation>>,
+        Read<'s, AssetStorage<BehaviorTree>>,
+        Read<'s, LazyUpdate>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (mut bosses, health, transforms, mut brains, mut melee, mut ranged, mut movement, mut hitstates, layers, main_assets, animations, behaviors, lazy, entities) : Self::SystemData) {
+        let named_animation = |name: &str| {
+            main_assets.animations.get(name)
+                .map(|handle| get_animation(&animations, handle))
+                .unwrap_or_else(|| panic!("No animation named {}", name))
+        };
+        for (boss, health, transform, entity) in (&mut bosses, &health, &transforms, &entities).join() {
+            if health.left <= 0 {
+                continue;
+            }
+            let fraction = health.left as f32 / health.max.max(1) as f32;
+            let target = boss.phases.iter().rposition(|phase| fraction <= phase.health);
+            let first = boss.phase.map(|phase| phase + 1).unwrap_or(0);
+            let target = match target {
+                Some(target) if target >= first => target,
+                _ => continue,
+            };
+            for phase in boss.phases[first..=target].iter() {
+                let tree = main_assets.behaviors.get(&phase.behavior)
+                    .map(|handle| get_behavior(&behaviors, handle))
+                    .unwrap_or_else(|| panic!("No behavior named {}", phase.behavior));
+                if let Some(brain) = brains.get_mut(entity) {
+                    brain.set_tree(tree);
+                } else {
+                    brains.insert(entity, Brain::new(tree));
+                }
+                if let Some(data) = phase.melee.as_ref() {
+                    let animation = named_animation(&data.animation);
+                    if let Some(melee) = melee.get_mut(entity) {
+                        melee.attack_animation = animation;
+                        melee.damage = data.damage;
+                    } else {
+                        melee.insert(entity, MeleeEnemy::new(animation, data.damage));
+                    }
+                }
+                if let Some(data) = phase.ranged.as_ref() {
+                    let animation = named_animation(&data.animation);
+                    if let Some(ranged) = ranged.get_mut(entity) {
+                        ranged.attack_animation = animation;
+                    } else {
+                        ranged.insert(entity, RangedEnemy::new(animation));
+                    }
+                }
+                if let (Some((wander_speed, chase_speed)), Some(movement)) = (phase.speeds, movement.get_mut(entity)) {
+                    movement.wander_speed = wander_speed;
+                    movement.chase_speed = chase_speed;
+                }
+                if let Some(hitstate) = hitstates.get_mut(entity) {
+                    for hitbox in phase.hitboxes.iter() {
+                        let layer = layers.named(&hitbox.layer);
+                        hitstate.set_hitbox(layer, Hitbox::new_shaped(hitbox.width, hitbox.height, hitbox.shape, hitbox.offset));
+                    }
+                }
+                for add in phase.adds.iter() {
+                    let enemy = add.enemy.clone();
+                    let x = transform.translation().x + add.offset.0;
+                    let y = transform.translation().y + add.offset.1;
+                    lazy.exec_mut(move |world| {
+                        if let Some(builder) = spawn_enemy(world, &enemy, x, y) {
+                            builder.build();
+                        }
+                    });
+                }
+            }
+            boss.phase = Some(target);
+        }
+    }
+}
+
+pub struct BossHealthBarSystem;
+impl<'s> System<'s> for BossHealthBarSystem {
+    type SystemData = (
+        ReadStorage<'s, Boss>,
+        ReadStorage<'s, Health>,
+        ReadStorage<'s, BossHealthPip>,
+        WriteStorage<'s, SpriteRender>,
+    );
+    fn run(&mut self, (bosses, health, pips, mut sprites) : Self::SystemData) {
+        let filled = (&bosses, &health).join()
+            .map(|(_, health)| {
+                let fraction = health.left.max(0) as f32 / health.max.max(1) as f32;
+                (fraction * BOSS_PIPS as f32).ceil() as usize
+            })
+            .next();
+        for (pip, sprite) in (&pips, &mut sprites).join() {
+            sprite.sprite_number = match filled {
+                Some(filled) if pip.index < filled => FULL_HEART,
+                Some(_) => EMPTY_HEART,
+                None => BLANK,
+            };
+        }
+    }
+}
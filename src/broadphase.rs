@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+pub type Bounds = (f32, f32, f32, f32);
+
+pub fn overlaps((ax0, ay0, ax1, ay1): Bounds, (bx0, by0, bx1, by1): Bounds) -> bool {
+    ax0 < bx1 && bx0 < ax1 && ay0 < by1 && by0 < ay1
+}
+
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    entries: Vec<(T, Bounds)>,
+}
+impl<T: Copy> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> SpatialHash<T> {
+        SpatialHash {
+            cell_size,
+            cells: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.entries.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    fn cell_range(&self, (x0, y0, x1, y1): Bounds) -> (i32, i32, i32, i32) {
+        (
+            (x0 / self.cell_size).floor() as i32,
+            (y0 / self.cell_size).floor() as i32,
+            (x1 / self.cell_size).floor() as i32,
+            (y1 / self.cell_size).floor() as i32,
+        )
+    }
+    pub fn insert(&mut self, item: T, bounds: Bounds) {
+        let index = self.entries.len();
+        self.entries.push((item, bounds));
+        let (cx0, cy0, cx1, cy1) = self.cell_range(bounds);
+        for cx in cx0..=cx1 {
+            for cy in cy0..=cy1 {
+                self.cells.entry((cx, cy)).or_insert_with(Vec::new).push(index);
+            }
+        }
+    }
+    pub fn query(&self, bounds: Bounds, found: &mut Vec<T>) {
+        let mut indices = Vec::new();
+        let (cx0, cy0, cx1, cy1) = self.cell_range(bounds);
+        for cx in cx0..=cx1 {
+            for cy in cy0..=cy1 {
+                if let Some(cell) = self.cells.get(&(cx, cy)) {
+                    indices.extend(cell.iter().cloned());
+                }
+            }
+        }
+        indices.sort();
+        indices.dedup();
+        for index in indices {
+            let (item, item_bounds) = self.entries[index];
+            if overlaps(bounds, item_bounds) {
+                found.push(item);
+            }
+        }
+    }
+}
+
+fn axis_times(a0: f32, a1: f32, b0: f32, b1: f32, d: f32) -> Option<(f32, f32)> {
+    if d > 0.0 {
+        Some(((b0 - a1) / d, (b1 - a0) / d))
+    } else if d < 0.0 {
+        Some(((b1 - a0) / d, (b0 - a1) / d))
+    } else if a0 < b1 && b0 < a1 {
+        Some((std::f32::NEG_INFINITY, std::f32::INFINITY))
+    } else {
+        None
+    }
+}
+
+pub fn sweep((ax0, ay0, ax1, ay1): Bounds, (dx, dy): (f32, f32), (bx0, by0, bx1, by1): Bounds) -> Option<(f32, (f32, f32))> {
+    if dx == 0.0 && dy == 0.0 {
+        return None;
+    }
+    let (entry_x, exit_x) = axis_times(ax0, ax1, bx0, bx1, dx)?;
+    let (entry_y, exit_y) = axis_times(ay0, ay1, by0, by1, dy)?;
+    let entry = entry_x.max(entry_y);
+    let exit = exit_x.min(exit_y);
+    if entry >= exit || entry < 0.0 || entry > 1.0 {
+        return None;
+    }
+    if entry_x > entry_y {
+        Some((entry, (-dx.signum(), 0.0)))
+    } else {
+        Some((entry, (0.0, -dy.signum())))
+    }
+}
@@ -1,13 +1,17 @@
 use amethyst::{
     prelude::*,
     ecs::*,
-    renderer::Rgba,
+    renderer::{Rgba, Hidden},
     core::*,
     input::*,
+    shrev::{EventChannel, ReaderId},
 };
 use crate::basics::*;
+use crate::simulation::FixedStep;
+use crate::sounds::*;
 use crate::player::*;
 use crate::enemies::*;
+use crate::projectiles::*;
 
 
 #[derive(Component, Debug)]
@@ -45,7 +49,7 @@ impl<'s> System<'s> for DeathSystem {
         WriteStorage<'s, AnimationController>,
         WriteStorage<'s, Health>,
         Entities<'s>,
-        Read<'s, Time>,
+        Read<'s, FixedStep>,
     );
     fn run(&mut self, (animation, mut health, mut entities, time) : Self::SystemData) {
         for (health, entity) in (&mut health, &entities).join() {
@@ -59,6 +63,64 @@ impl<'s> System<'s> for DeathSystem {
     }
 }
 
+const BLINK_RATE: f32 = 10.0;
+
+pub struct InvulnerabilityBlinkSystem;
+impl<'s> System<'s> for InvulnerabilityBlinkSystem {
+    type SystemData = (
+        ReadStorage<'s, Health>,
+        WriteStorage<'s, Hidden>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (health, mut hidden, entities) : Self::SystemData) {
+        for (health, entity) in (&health, &entities).join() {
+            let blinking = health.invuln > 0.0 && (health.invuln * BLINK_RATE) as i32 % 2 == 1;
+            if blinking {
+                if !hidden.contains(entity) {
+                    hidden.insert(entity, Hidden);
+                }
+            } else if hidden.contains(entity) {
+                hidden.remove(entity);
+            }
+        }
+    }
+}
+
+pub struct FrameEventSystem {
+    reader: Option<ReaderId<AnimationEvent>>,
+}
+impl FrameEventSystem {
+    pub fn new() -> FrameEventSystem {
+        FrameEventSystem { reader: None }
+    }
+}
+impl<'s> System<'s> for FrameEventSystem {
+    type SystemData = (
+        Read<'s, EventChannel<AnimationEvent>>,
+        WriteStorage<'s, Health>,
+        Write<'s, ScreenShake>,
+    );
+    fn run(&mut self, (events, mut health, mut shake) : Self::SystemData) {
+        for animation_event in events.read(self.reader.as_mut().unwrap()) {
+            match animation_event.event {
+                FrameEvent::Invulnerable(duration) => {
+                    if let Some(health) = health.get_mut(animation_event.entity) {
+                        health.invuln = health.invuln.max(duration);
+                    }
+                },
+                FrameEvent::ScreenShake { magnitude, duration } => {
+                    shake.shake(magnitude, duration);
+                },
+                _ => {}
+            }
+        }
+    }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<AnimationEvent>>().register_reader());
+    }
+}
+
 #[derive(Component, Debug)]
 #[storage(HashMapStorage)]
 pub struct StaggerAnimation {
@@ -70,7 +132,14 @@ impl StaggerAnimation {
     }
 }
 
-pub struct PlayerDamageSystem;
+pub struct PlayerDamageSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+}
+impl PlayerDamageSystem {
+    pub fn new() -> PlayerDamageSystem {
+        PlayerDamageSystem { contacts: None }
+    }
+}
 fn knockback(distance: f32) -> HitboxAnimation {
     let speed = 100.0;
     let mut animation = HitboxAnimation::new();
@@ -83,6 +152,13 @@ fn stagger_entity<'s>(entity: Entity, animations: &mut WriteStorage<'s, Animatio
         animation.start(stagger_animation, AnimationState::Staggered);
     }
 }
+pub fn is_invulnerable<'s>(entity: Entity, health: &WriteStorage<'s, Health>) -> bool {
+    if let Some(health) = health.get(entity) {
+        health.invuln > 0.0
+    } else {
+        false
+    }
+}
 pub fn is_staggered<'s>(entity: Entity, animations: &WriteStorage<'s, AnimationController>) -> bool {
     if let Some(animation) = animations.get(entity) {
         animation.state() == AnimationState::Staggered
@@ -94,33 +170,22 @@ fn knockback_entity<'s>(collision: HitboxCollision, entity: Entity, animations:
     stagger_entity(entity, animations, knockback(15.0));
     if let Some(current_rotation) = rotation.get(entity) {
         let (dx, dy, _depthx, _depthy) = collision;
-        rotation.insert(entity, {
-            if dx.abs() > dy.abs() {
-                if dx < 0.0 {
-                    Rotation::East
-                } else {
-                    Rotation::West
-                }
-            } else {
-                if dy < 0.0 {
-                    Rotation::North
-                } else {
-                    Rotation::South
-                }
-            }
-        });
+        rotation.insert(entity, Rotation::toward(-dx, -dy));
     }
 }
 impl<'s> System<'s> for PlayerDamageSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
     fn run(&mut self, system_data: Self::SystemData) {
         self.check_collisions(system_data);
     }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
+    }
 }
 impl<'s> HitboxCollisionSystem<'s> for PlayerDamageSystem {
     type ExtraData = (
@@ -130,13 +195,19 @@ impl<'s> HitboxCollisionSystem<'s> for PlayerDamageSystem {
         ReadStorage<'s, Player>,
         ReadStorage<'s, Velocity>,
         ReadStorage<'s, StaggerAnimation>,
+        Write<'s, EventChannel<Sound>>,
+        WriteStorage<'s, Projectile>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+        if phase == ContactPhase::Exit {
+            return;
+        }
         let animations = &mut extra.0;
         let rotation = &mut extra.1;
         let velocity = &mut extra.4;
         let stagger_animation = &mut extra.5;
-        if !is_staggered(entity_b, animations) {
+        let projectile = extra.7.get_mut(entity_a);
+        if !is_staggered(entity_b, animations) && !is_invulnerable(entity_b, &extra.2) {
             if let Some(stagger_animation) = stagger_animation.get(entity_b) {
                 stagger_entity(entity_b, animations, stagger_animation.stagger.clone());
             } else if let Some(velocity) = velocity.get(entity_b) {
@@ -144,29 +215,48 @@ impl<'s> HitboxCollisionSystem<'s> for PlayerDamageSystem {
             }
             let health = &mut extra.2;
             let player = &extra.3;
-            if let (Some(player), Some(health)) = (player.get(entity_a), health.get_mut(entity_b)) {
-                health.left -= 1;
+            let damage = match (player.get(entity_a), &projectile) {
+                (Some(_), _) => Some(1),
+                (None, Some(projectile)) => Some(projectile.damage),
+                _ => None,
+            };
+            if let (Some(damage), Some(health)) = (damage, health.get_mut(entity_b)) {
+                health.left -= damage;
+                extra.6.single_write(Sound::HitEnemy);
             }
         }
+        if let Some(projectile) = projectile {
+            projectile.spent = true;
+        }
     }
-    fn source() -> usize {
-        PLAYER_ATTACK_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        PLAYER_HITTABLE_BOX
+    fn interaction() -> Interaction {
+        Interaction::PlayerDamage
+    }
+}
+pub struct EnemyDamageSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+}
+impl EnemyDamageSystem {
+    pub fn new() -> EnemyDamageSystem {
+        EnemyDamageSystem { contacts: None }
     }
 }
-pub struct EnemyDamageSystem;
 impl<'s> System<'s> for EnemyDamageSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
     fn run(&mut self, system_data: Self::SystemData) {
         self.check_collisions(system_data);
     }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
+    }
 }
 impl<'s> HitboxCollisionSystem<'s> for EnemyDamageSystem {
     type ExtraData = (
@@ -176,13 +266,19 @@ impl<'s> HitboxCollisionSystem<'s> for EnemyDamageSystem {
         ReadStorage<'s, MeleeEnemy>,
         ReadStorage<'s, Velocity>,
         ReadStorage<'s, StaggerAnimation>,
+        Write<'s, EventChannel<Sound>>,
+        WriteStorage<'s, Projectile>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+        if phase == ContactPhase::Exit {
+            return;
+        }
         let animations = &mut extra.0;
         let rotation = &mut extra.1;
         let velocity = &mut extra.4;
         let stagger_animation = &mut extra.5;
-        if !is_staggered(entity_b, animations) {
+        let projectile = extra.7.get_mut(entity_a);
+        if !is_staggered(entity_b, animations) && !is_invulnerable(entity_b, &extra.2) {
             if let Some(stagger_animation) = stagger_animation.get(entity_b) {
                 stagger_entity(entity_b, animations, stagger_animation.stagger.clone());
             } else if let Some(velocity) = velocity.get(entity_b) {
@@ -190,15 +286,24 @@ impl<'s> HitboxCollisionSystem<'s> for EnemyDamageSystem {
             }
             let health = &mut extra.2;
             let enemy = &extra.3;
-            if let (Some(enemy), Some(health)) = (enemy.get(entity_a), health.get_mut(entity_b)) {
-                health.hit_for(enemy.damage, 1.0);
+            let damage = match (enemy.get(entity_a), &projectile) {
+                (Some(enemy), _) => Some(enemy.damage),
+                (None, Some(projectile)) => Some(projectile.damage),
+                _ => None,
+            };
+            if let (Some(damage), Some(health)) = (damage, health.get_mut(entity_b)) {
+                health.hit_for(damage, 1.0);
+                extra.6.single_write(Sound::HitPlayer);
             }
         }
+        if let Some(projectile) = projectile {
+            projectile.spent = true;
+        }
     }
-    fn source() -> usize {
-        ENEMY_ATTACK_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        ENEMY_HITTABLE_BOX
+    fn interaction() -> Interaction {
+        Interaction::EnemyDamage
     }
 }
@@ -4,13 +4,16 @@ use amethyst::{
     renderer::{SpriteRender, SpriteSheetHandle, Rgba},
     core::*,
     input::*,
+    shrev::{EventChannel, ReaderId},
 };
 use crate::basics::*;
+use crate::replay::PlayerInput;
 use crate::combat::*;
 use crate::world::*;
 use crate::sprites::*;
 use crate::utils::*;
 use crate::player::*;
+use crate::sounds::*;
 
 #[derive(Debug, Clone)]
 pub enum Upgrade {
@@ -28,8 +31,11 @@ pub struct Chest {
 
 pub fn spawn_chest(world: &mut World, x: f32, y: f32, cost: i32, upgrade: Upgrade) {
     let mut hitboxes = HitState::new();
-    hitboxes.set(PLAYER_HITTABLE_BOX, 16.0, 16.0, (0.0, 0.0));
-    hitboxes.set(CHEST_BOX, 16.0, 16.0, (0.0, 0.0));
+    {
+        let layers = world.read_resource::<CollisionLayers>();
+        hitboxes.set(layers.named("player_hittable"), 16.0, 16.0, (0.0, 0.0));
+        hitboxes.set(layers.named("chest"), 16.0, 16.0, (0.0, 0.0));
+    }
     let sprite_sheet = get_sprite_sheet(world);
     let chest = spawn_at(world, x, y)
         .with_sprite(sprite_sheet, CHEST_SPRITE)
@@ -52,20 +58,26 @@ pub fn spawn_chest(world: &mut World, x: f32, y: f32, cost: i32, upgrade: Upgrad
             .build();
     }
 }
-pub struct PurchaseSystem;
+pub struct PurchaseSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+}
+impl PurchaseSystem {
+    pub fn new() -> PurchaseSystem {
+        PurchaseSystem { contacts: None }
+    }
+}
 impl<'s> System<'s> for PurchaseSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
-    fn run(&mut self, mut system_data: Self::SystemData) {
-        let extra = &mut system_data.3;
-        let input = &extra.3;
-        if let Some(true) = input.action_is_down("interact") {
-            self.check_collisions(system_data);
-        }
+    fn run(&mut self, system_data: Self::SystemData) {
+        self.check_collisions(system_data);
+    }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
     }
 }
 impl<'s> HitboxCollisionSystem<'s> for PurchaseSystem {
@@ -73,9 +85,13 @@ impl<'s> HitboxCollisionSystem<'s> for PurchaseSystem {
         WriteStorage<'s, Health>,
         WriteStorage<'s, Player>,
         ReadStorage<'s, Chest>,
-        Read<'s, InputHandler<String, String>>,
+        Read<'s, PlayerInput>,
+        Write<'s, EventChannel<Sound>>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+        if phase == ContactPhase::Exit || !extra.3.interact {
+            return;
+        }
         let health = &mut extra.0;
         if let Some(chest_health) = health.get_mut(entity_a) {
             chest_health.hit_for(99, 0.0);
@@ -94,10 +110,12 @@ impl<'s> HitboxCollisionSystem<'s> for PurchaseSystem {
                         health.pay(cost);
                         health.embiggen();
                         player.big_hearts = true;
+                        extra.4.single_write(Sound::Purchase);
                     },
                     (Upgrade::HeartBracelet, _, false) => {
                         health.pay(cost);
                         player.healthy = true;
+                        extra.4.single_write(Sound::Purchase);
                     },
                     _ => {
 
@@ -106,10 +124,10 @@ impl<'s> HitboxCollisionSystem<'s> for PurchaseSystem {
             }
         }
     }
-    fn source() -> usize {
-        CHEST_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        PLAYER_INTERACT_BOX
+    fn interaction() -> Interaction {
+        Interaction::Purchase
     }
 }
@@ -0,0 +1,209 @@
+use amethyst::{
+    prelude::*,
+    renderer::SpriteSheetHandle,
+};
+use rand::Rng;
+use crate::boss::spawn_boss;
+use crate::drops::*;
+use crate::enemies::spawn_enemy;
+use crate::sprites::*;
+use crate::world::*;
+
+const BASE_SIZE: i32 = 32;
+const SIZE_PER_LEVEL: i32 = 4;
+const MAX_GROWTH: i32 = 6;
+const BASE_ROOMS: i32 = 4;
+const MAX_ROOMS: i32 = 10;
+const ROOM_SIZE: (i32, i32) = (5, 10);
+const ROOM_ATTEMPTS: usize = 200;
+const CORRIDOR_WIDTH: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Room {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+impl Room {
+    pub fn center(&self) -> (i32, i32) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+    fn overlaps(&self, other: &Room, margin: i32) -> bool {
+        self.x - margin < other.x + other.width
+            && other.x - margin < self.x + self.width
+            && self.y - margin < other.y + other.height
+            && other.y - margin < self.y + self.height
+    }
+    fn random_tile<R: Rng>(&self, rng: &mut R) -> (i32, i32) {
+        (
+            rng.gen_range(self.x + 1, self.x + self.width - 1),
+            rng.gen_range(self.y + 1, self.y + self.height - 1),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DungeonLayout {
+    pub width: i32,
+    pub height: i32,
+    pub floor: Vec<bool>,
+    pub rooms: Vec<Room>,
+    pub player_start: (i32, i32),
+    pub portal: (i32, i32),
+    pub boss: (i32, i32),
+    pub enemies: Vec<(String, (i32, i32))>,
+    pub chests: Vec<((i32, i32), i32, Upgrade)>,
+}
+impl DungeonLayout {
+    fn new(width: i32, height: i32) -> DungeonLayout {
+        DungeonLayout {
+            width,
+            height,
+            floor: vec![false; (width * height) as usize],
+            rooms: Vec::new(),
+            player_start: (0, 0),
+            portal: (0, 0),
+            boss: (0, 0),
+            enemies: Vec::new(),
+            chests: Vec::new(),
+        }
+    }
+    pub fn is_floor(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height && self.floor[(y * self.width + x) as usize]
+    }
+    fn carve(&mut self, x: i32, y: i32) {
+        if x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1 {
+            self.floor[(y * self.width + x) as usize] = true;
+        }
+    }
+    fn carve_room(&mut self, room: Room) {
+        for x in room.x..(room.x + room.width) {
+            for y in room.y..(room.y + room.height) {
+                self.carve(x, y);
+            }
+        }
+    }
+    fn carve_corridor<R: Rng>(&mut self, rng: &mut R, (ax, ay): (i32, i32), (bx, by): (i32, i32)) {
+        let corner = if rng.gen_bool(0.5) { (bx, ay) } else { (ax, by) };
+        for &((x0, y0), (x1, y1)) in [((ax, ay), corner), (corner, (bx, by))].iter() {
+            for x in x0.min(x1)..=x0.max(x1) {
+                for y in y0.min(y1)..=y0.max(y1) {
+                    for offset in 0..CORRIDOR_WIDTH {
+                        if y0 == y1 {
+                            self.carve(x, y + offset);
+                        } else {
+                            self.carve(x + offset, y);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn generate<R: Rng>(rng: &mut R, levels: i32) -> DungeonLayout {
+    let growth = levels.max(0).min(MAX_GROWTH);
+    let size = BASE_SIZE + SIZE_PER_LEVEL * growth;
+    let room_count = (BASE_ROOMS + levels.max(0)).min(MAX_ROOMS);
+    let mut layout = DungeonLayout::new(size, size);
+    for _ in 0..ROOM_ATTEMPTS {
+        if layout.rooms.len() as i32 >= room_count {
+            break;
+        }
+        let width = rng.gen_range(ROOM_SIZE.0, ROOM_SIZE.1);
+        let height = rng.gen_range(ROOM_SIZE.0, ROOM_SIZE.1);
+        let room = Room {
+            x: rng.gen_range(1, size - width - 1),
+            y: rng.gen_range(1, size - height - 1),
+            width,
+            height,
+        };
+        if layout.rooms.iter().all(|other| !room.overlaps(other, 2)) {
+            layout.rooms.push(room);
+        }
+    }
+    let rooms = layout.rooms.clone();
+    for room in rooms.iter() {
+        layout.carve_room(*room);
+    }
+    for pair in rooms.windows(2) {
+        layout.carve_corridor(rng, pair[0].center(), pair[1].center());
+    }
+    layout.player_start = rooms[0].center();
+    let start = layout.player_start;
+    let portal_room = *rooms.iter()
+        .max_by_key(|room| {
+            let (x, y) = room.center();
+            (x - start.0).abs() + (y - start.1).abs()
+        })
+        .unwrap();
+    layout.portal = portal_room.center();
+    layout.boss = portal_room.random_tile(rng);
+    while layout.boss == layout.portal {
+        layout.boss = portal_room.random_tile(rng);
+    }
+
+    let per_room = (1 + levels.max(0) / 2).min(4);
+    for room in rooms.iter().skip(1) {
+        for _ in 0..per_room {
+            let tile = room.random_tile(rng);
+            if tile == layout.portal || tile == layout.boss {
+                continue;
+            }
+            let name = if levels >= 2 && rng.gen_bool(0.3) { "goblin_archer" } else { "goblin" };
+            layout.enemies.push((name.to_string(), tile));
+        }
+    }
+
+    let mut chest_rooms: Vec<Room> = rooms.iter()
+        .skip(1)
+        .filter(|room| **room != portal_room)
+        .cloned()
+        .collect();
+    if chest_rooms.is_empty() {
+        chest_rooms.push(rooms[0]);
+    }
+    for (cost, upgrade) in vec![(1, Upgrade::HeartBracelet), (2, Upgrade::GoldenAegis)] {
+        let room = chest_rooms[rng.gen_range(0, chest_rooms.len())];
+        let tile = room.random_tile(rng);
+        if !layout.enemies.iter().any(|(_, enemy)| *enemy == tile) {
+            layout.chests.push((tile, cost, upgrade));
+        }
+    }
+    layout
+}
+
+pub fn draw_dungeon(world: &mut World, sprite_sheet: &SpriteSheetHandle, layout: &DungeonLayout) {
+    for x in 0..layout.width {
+        for y in 0..layout.height {
+            if layout.is_floor(x, y) {
+                spawn_world_tile(world, sprite_sheet.clone(), x, y, FLOOR_EMPTY).build();
+            } else {
+                draw_wall(world, sprite_sheet, (x, y), (1, 1), WALL);
+            }
+        }
+    }
+}
+
+pub fn populate_dungeon(world: &mut World, layout: &DungeonLayout, boss: Option<&str>) {
+    let (px, py) = TileMap::tile_center(layout.portal.0, layout.portal.1);
+    portal(world, px, py).build();
+    if let Some(boss) = boss {
+        let (bx, by) = TileMap::tile_center(layout.boss.0, layout.boss.1);
+        spawn_boss(world, boss, bx, by);
+    }
+    for (name, (x, y)) in layout.enemies.iter() {
+        let (ex, ey) = TileMap::tile_center(*x, *y);
+        if let Some(builder) = spawn_enemy(world, name, ex, ey) {
+            builder.build();
+        }
+    }
+    for ((x, y), cost, upgrade) in layout.chests.iter() {
+        let (cx, cy) = TileMap::tile_center(*x, *y);
+        spawn_chest(world, cx, cy, *cost, upgrade.clone());
+    }
+}
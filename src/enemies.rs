@@ -1,15 +1,22 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
 use amethyst::{
     prelude::*,
     ecs::*,
     renderer::{SpriteRender, SpriteSheetHandle, Rgba},
     core::*,
     input::*,
+    shrev::{EventChannel, ReaderId},
 };
 use crate::basics::*;
 use crate::combat::*;
 use crate::player::*;
 use crate::sprites::*;
 use crate::utils::*;
+use crate::physics::*;
+use crate::world::TileMap;
+use crate::broadphase::Bounds;
+use crate::ai::Brain;
 #[derive(Component, Debug)]
 #[storage(HashMapStorage)]
 pub struct Enemy {
@@ -28,8 +35,8 @@ impl Enemy {
 #[derive(Component, Debug)]
 #[storage(HashMapStorage)]
 pub struct MeleeEnemy {
-    in_melee: bool,
-    attack_animation: HitboxAnimation,
+    pub in_melee: bool,
+    pub attack_animation: HitboxAnimation,
     pub damage: i32,
 }
 impl MeleeEnemy {
@@ -42,21 +49,40 @@ impl MeleeEnemy {
     }
 }
 
+#[derive(Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct RangedEnemy {
+    pub aligned: bool,
+    pub attack_animation: HitboxAnimation,
+}
+impl RangedEnemy {
+    pub fn new(attack_animation: HitboxAnimation) -> RangedEnemy {
+        RangedEnemy {
+            aligned: false,
+            attack_animation,
+        }
+    }
+}
+
 #[derive(Component, Debug)]
 #[storage(VecStorage)]
 pub struct ChaseAndWanderEnemy {
     aware_of_player: Option<(f32, f32)>,
-    idle_animation: HitboxAnimation,
-    walking_animation: HitboxAnimation,
-    wander_speed: f32,
-    chase_speed: f32,
-    wander_direction: Option<(f32, f32)>,
-    wander_progress: f32,
+    awareness: f32,
+    pub search_left: Option<f32>,
+    pub idle_animation: HitboxAnimation,
+    pub walking_animation: HitboxAnimation,
+    pub wander_speed: f32,
+    pub chase_speed: f32,
+    pub wander_direction: Option<(f32, f32)>,
+    pub wander_progress: f32,
 }
 impl ChaseAndWanderEnemy {
     pub fn new(idle_animation: HitboxAnimation, walking_animation: HitboxAnimation, wander_speed: f32, chase_speed: f32) -> ChaseAndWanderEnemy {
         ChaseAndWanderEnemy {
             aware_of_player: None,
+            awareness: 0.0,
+            search_left: None,
             idle_animation,
             walking_animation,
             wander_speed,
@@ -65,221 +91,272 @@ impl ChaseAndWanderEnemy {
             wander_progress: -1.0,
         }
     }
-}
-const wanders: [(f32, f32); 4] = [
-    ( 1.0,  0.0),
-    (-1.0,  0.0),
-    ( 0.0,  1.0),
-    ( 0.0, -1.0),
-];
-pub struct ChaseAndWanderSystem;
-impl ChaseAndWanderSystem {
-    fn walk<'s>(
-        rotations: &mut WriteStorage<'s, Rotation>,
-        animation_controller: &mut AnimationController,
-        animation: HitboxAnimation,
-        velocity: &mut Velocity,
-        entity: Entity,
-        (wx, wy): (f32, f32)) {
-        animation_controller.start(animation, AnimationState::Walking);
-        velocity.vx = wx;
-        velocity.vy = wy;
-        let rotation = {
-            if wx.abs() > wy.abs() {
-                if wx > 0.0 {
-                    Rotation::East
-                } else {
-                    Rotation::West
-                }
-            } else {
-                if wy > 0.0 {
-                    Rotation::North
-                } else {
-                    Rotation::South
-                }
-            }
-        };
-        if let Ok(_) = rotations.insert(entity, rotation) {
-        }
+    pub fn notice(&mut self, player_position: (f32, f32)) {
+        self.aware_of_player = Some(player_position);
+        self.awareness = AWARENESS_DURATION;
+        self.search_left = None;
+    }
+    pub fn forget(&mut self) {
+        self.aware_of_player = None;
+        self.awareness = 0.0;
+        self.search_left = None;
+        self.wander_direction = None;
+    }
+    pub fn decay(&mut self, dt: f32) {
+        self.awareness -= dt;
+    }
+    pub fn last_known_position(&self) -> Option<(f32, f32)> {
+        self.aware_of_player
     }
-    fn wander<'s>(
-        rotations: &mut WriteStorage<'s, Rotation>,
-        animation_controller: &mut AnimationController,
-        velocity: &mut Velocity,
-        enemy: &ChaseAndWanderEnemy,
-        entity: Entity,
-        (wx, wy): (f32, f32)
-    ) {
-        ChaseAndWanderSystem::walk(
-            rotations,
-            animation_controller,
-            enemy.walking_animation.clone(),
-            velocity,
-            entity,
-            (wx * enemy.wander_speed, wy * enemy.wander_speed),
-        );
+    pub fn is_tracking(&self) -> bool {
+        self.aware_of_player.is_some() && self.awareness > 0.0
     }
 }
-impl<'s> System<'s> for ChaseAndWanderSystem {
-    type SystemData = (
-        WriteStorage<'s, AnimationController>,
-        WriteStorage<'s, ChaseAndWanderEnemy>,
-        WriteStorage<'s, MeleeEnemy>,
-        ReadStorage<'s, Transform>,
-        WriteStorage<'s, Rotation>,
-        WriteStorage<'s, Velocity>,
-        ReadStorage<'s, Player>,
-        Read<'s, Time>,
-        Entities<'s>,
-    );
-    fn run(&mut self, (mut animation, mut enemy, mut melee, transform, mut rotations, mut velocity, player, time, entities) : Self::SystemData) {
-        for (mut animation, mut enemy, transform, mut velocity, entity) in (&mut animation, &mut enemy, &transform, &mut velocity, &entities).join() {
-            if animation.state() == AnimationState::Idle || animation.state() == AnimationState::Walking {
-                if let Some(player_position) = enemy.aware_of_player {
-                    let mut chase = true;
-                    if let Some(melee) = melee.get(entity) {
-                        if melee.in_melee {
-                            println!("Attacking");
-                            animation.start(melee.attack_animation.clone(), AnimationState::Attacking);
-                            chase = false;
-                        }
-                    }
-                    if chase {
-                        let dx = player_position.0 - transform.translation().x;
-                        let dy = player_position.1 - transform.translation().y;
-                        if length(dx, dy) > 12.0 {
-                            let dir = normalize(dx, dy);
-                            let (wx, wy) = (dir.0 * enemy.chase_speed, dir.1 * enemy.chase_speed);
-                            ChaseAndWanderSystem::walk(&mut rotations, animation, enemy.walking_animation.clone(), velocity, entity, (wx, wy));
-                        } else {
-                            animation.start(enemy.idle_animation.clone(), AnimationState::Idle);
-                            velocity.vx = 0.0;
-                            velocity.vy = 0.0;
-                        }
-                    }
-                } else if let Some((wx, wy)) = enemy.wander_direction {
-                    enemy.wander_progress -= time.delta_seconds();
-                    if enemy.wander_progress <= 0.0 {
-                        println!("Stopping");
-                        animation.start(enemy.idle_animation.clone(), AnimationState::Idle);
-                        velocity.vx = 0.0;
-                        velocity.vy = 0.0;
-                        enemy.wander_progress = random_between(2.0, 5.0);
-                        enemy.wander_direction = None;
-                    } else if animation.state() != AnimationState::Walking {
-                        ChaseAndWanderSystem::wander(&mut rotations, animation, velocity, enemy, entity, (wx, wy));
-                    }
-                } else {
-                    enemy.wander_progress -= time.delta_seconds();
-                    if enemy.wander_progress <= 0.0 {
-                        enemy.wander_progress = random_between(1.0, 3.0);
-                        enemy.wander_direction = wanders.choose(&mut rng()).cloned()
-                    }
-                }
-            }
-        }
+const AWARENESS_DURATION: f32 = 2.0;
+pub struct SightSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+    obstacles: Vec<Bounds>,
+}
+impl SightSystem {
+    pub fn new() -> SightSystem {
+        SightSystem { contacts: None, obstacles: Vec::new() }
     }
 }
-pub struct SightSystem;
 impl<'s> System<'s> for SightSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
     fn run(&mut self, system_data: Self::SystemData) {
         self.check_collisions(system_data);
     }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
+    }
 }
 impl<'s> HitboxCollisionSystem<'s> for SightSystem {
     type ExtraData = (
         WriteStorage<'s, AnimationController>,
         WriteStorage<'s, ChaseAndWanderEnemy>,
+        ReadStorage<'s, Physical>,
+        Read<'s, TileMap>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn prepare(&mut self, transforms: &ReadStorage<'s, Transform>, extra: &Self::ExtraData) {
+        self.obstacles = static_obstacles(transforms, &extra.2);
+    }
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+        if phase == ContactPhase::Exit {
+            return;
+        }
         let animations = &mut extra.0;
         let enemy = &mut extra.1;
-        if let (Some(animation), Some(enemy), Some(player_loc)) =
-            (animations.get_mut(entity_a), enemy.get_mut(entity_a), transforms.get(entity_b)) {
-            enemy.aware_of_player = Some((player_loc.translation().x, player_loc.translation().y))
+        if let (Some(animation), Some(enemy), Some(enemy_loc), Some(player_loc)) =
+            (animations.get_mut(entity_a), enemy.get_mut(entity_a), transforms.get(entity_a), transforms.get(entity_b)) {
+            let from = (enemy_loc.translation().x, enemy_loc.translation().y);
+            let to = (player_loc.translation().x, player_loc.translation().y);
+            if line_of_sight(&extra.3, &self.obstacles, from, to) {
+                enemy.notice(to);
+            }
         }
     }
-    fn source() -> usize {
-        ENEMY_SIGHT_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        ENEMY_HITTABLE_BOX
+    fn interaction() -> Interaction {
+        Interaction::Sight
+    }
+}
+pub struct AimingSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+}
+impl AimingSystem {
+    pub fn new() -> AimingSystem {
+        AimingSystem { contacts: None }
     }
 }
-pub struct AimingSystem;
 impl<'s> System<'s> for AimingSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
-    fn run(&mut self, mut system_data: Self::SystemData) {
-        let extra = &mut system_data.3;
-        for (mut enemy) in (&mut (extra.1)).join() {
-            enemy.in_melee = false;
-        }
+    fn run(&mut self, system_data: Self::SystemData) {
         self.check_collisions(system_data);
     }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
+    }
 }
 impl<'s> HitboxCollisionSystem<'s> for AimingSystem {
     type ExtraData = (
         WriteStorage<'s, AnimationController>,
         WriteStorage<'s, MeleeEnemy>,
+        WriteStorage<'s, RangedEnemy>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
         let animations = &mut extra.0;
         let enemy = &mut extra.1;
         if let (Some(animation), Some(enemy), Some(player_loc)) =
             (animations.get_mut(entity_a), enemy.get_mut(entity_a), transforms.get(entity_b)) {
-            enemy.in_melee = true;
+            enemy.in_melee = phase != ContactPhase::Exit;
+        }
+        if let Some(ranged) = extra.2.get_mut(entity_a) {
+            ranged.aligned = phase != ContactPhase::Exit;
         }
     }
-    fn source() -> usize {
-        ENEMY_AIMING_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        ENEMY_HITTABLE_BOX
+    fn interaction() -> Interaction {
+        Interaction::Aiming
     }
 }
 
-pub fn spawn_goblin(world: &mut World, x: f32, y: f32) -> EntityBuilder {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovementData {
+    pub idle: usize,
+    pub walk: (usize, usize),
+    #[serde(default = "default_walk_duration")]
+    pub walk_duration: f32,
+    pub wander_speed: f32,
+    pub chase_speed: f32,
+}
+fn default_walk_duration() -> f32 {
+    0.1
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeleeData {
+    pub damage: i32,
+    pub animation: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangedData {
+    pub animation: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnemyArchetype {
+    pub sprite: usize,
+    pub size: f32,
+    pub health: i32,
+    #[serde(default)]
+    pub invisible: bool,
+    #[serde(default)]
+    pub hitboxes: Vec<FrameHitboxData>,
+    #[serde(default)]
+    pub movement: Option<MovementData>,
+    #[serde(default)]
+    pub melee: Option<MeleeData>,
+    #[serde(default)]
+    pub ranged: Option<RangedData>,
+    #[serde(default)]
+    pub behavior: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnemyArchetypes {
+    #[serde(default)]
+    pub archetypes: BTreeMap<String, EnemyArchetype>,
+}
+impl EnemyArchetypes {
+    pub fn get(&self, name: &str) -> Option<&EnemyArchetype> {
+        self.archetypes.get(name)
+    }
+    pub fn animation_names(&self) -> BTreeSet<String> {
+        let melee = self.archetypes.values()
+            .filter_map(|archetype| archetype.melee.as_ref().map(|melee| melee.animation.clone()));
+        let ranged = self.archetypes.values()
+            .filter_map(|archetype| archetype.ranged.as_ref().map(|ranged| ranged.animation.clone()));
+        melee.chain(ranged).collect()
+    }
+    pub fn behavior_names(&self) -> BTreeSet<String> {
+        self.archetypes.values()
+            .filter_map(|archetype| archetype.behavior.clone())
+            .collect()
+    }
+    pub fn validate(&self, layers: &CollisionLayers) -> Result<(), String> {
+        for (name, archetype) in self.archetypes.iter() {
+            let owner = format!("Enemy {}", name);
+            check_hitbox_layers(&owner, &archetype.hitboxes, layers)?;
+            if let Some(melee) = archetype.melee.as_ref() {
+                check_animation(&owner, &melee.animation)?;
+            }
+            if let Some(ranged) = archetype.ranged.as_ref() {
+                check_animation(&owner, &ranged.animation)?;
+            }
+            if let Some(behavior) = archetype.behavior.as_ref() {
+                check_behavior(&owner, behavior)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn check_hitbox_layers(owner: &str, hitboxes: &[FrameHitboxData], layers: &CollisionLayers) -> Result<(), String> {
+    match hitboxes.iter().find(|hitbox| layers.layer(&hitbox.layer).is_none()) {
+        Some(hitbox) => Err(format!("{} uses unknown hitbox layer {}", owner, hitbox.layer)),
+        None => Ok(()),
+    }
+}
+pub fn check_animation(owner: &str, name: &str) -> Result<(), String> {
+    if Path::new(&get_resource(&format!("animations/{}.ron", name))).exists() {
+        Ok(())
+    } else {
+        Err(format!("{} uses unknown animation {}", owner, name))
+    }
+}
+pub fn check_behavior(owner: &str, name: &str) -> Result<(), String> {
+    if Path::new(&get_resource(&format!("ai/{}.ron", name))).exists() {
+        Ok(())
+    } else {
+        Err(format!("{} uses unknown behavior {}", owner, name))
+    }
+}
+
+pub fn spawn_enemy(world: &mut World, name: &str, x: f32, y: f32) -> Option<EntityBuilder> {
+    let archetype = world.read_resource::<EnemyArchetypes>().get(name).cloned()?;
     let sprite_sheet = get_sprite_sheet(world);
-    let idle = idle_animation(GOBLIN_IDLE);
-    let walking = walking_animation(GOBLIN_IDLE, GOBLIN_WALK_0, GOBLIN_WALK_1, 0.1);
-    let mut attack_animation = HitboxAnimation::new();
-    let frame = attack_animation.add_frame_with_velocity((0.0, 0.0), 0.125);
-    attack_animation.set_sprite(frame, GOBLIN_ATTACK_0);
-    let frame = attack_animation.add_frame_with_velocity((50.0, 0.0), 0.125);
-    attack_animation.set_sprite(frame, GOBLIN_ATTACK_1);
-    attack_animation.set_hitbox(frame, ENEMY_ATTACK_BOX, Hitbox::new_at(4.0, (8.0, 2.0)));
-    let frame = attack_animation.add_frame_with_velocity((50.0, 0.0), 0.375);
-    attack_animation.set_sprite(frame, GOBLIN_ATTACK_2);
-    let frame = attack_animation.add_frame_with_velocity((50.0, 0.0), 0.125);
-    attack_animation.set_sprite(frame, GOBLIN_ATTACK_3);
-    attack_animation.set_hitbox(frame, ENEMY_ATTACK_BOX, Hitbox::new_at(4.0, (8.0, -2.0)));
-    let frame = attack_animation.add_frame_with_velocity((50.0, 0.0), 0.375);
-    attack_animation.set_sprite(frame, GOBLIN_ATTACK_4);
-    let frame = attack_animation.add_frame_with_velocity((0.0, 0.0), 0.5);
-    attack_animation.set_sprite(frame, GOBLIN_IDLE);
     let mut hitstate = HitState::new();
-    hitstate.set(ENEMY_AIMING_BOX, 20.0, 10.0, (16.0, 0.0));
-    hitstate.set(ENEMY_SIGHT_BOX, 64.0, 64.0, (20.0, 0.0));
-    hitstate.set(PLAYER_HITTABLE_BOX, 12.0, 12.0, (0.0, 0.0));
+    {
+        let layers = world.read_resource::<CollisionLayers>();
+        for hitbox in archetype.hitboxes.iter() {
+            let layer = layers.named(&hitbox.layer);
+            hitstate.set_hitbox(layer, Hitbox::new_shaped(hitbox.width, hitbox.height, hitbox.shape, hitbox.offset));
+        }
+    }
+    let melee = archetype.melee.as_ref()
+        .map(|melee| MeleeEnemy::new(get_named_animation(world, &melee.animation), melee.damage));
+    let ranged = archetype.ranged.as_ref()
+        .map(|ranged| RangedEnemy::new(get_named_animation(world, &ranged.animation)));
+    let behavior = archetype.behavior.as_ref()
+        .map(|behavior| Brain::new(get_named_behavior(world, behavior)));
+    let enemy = if archetype.invisible { Enemy::new_invisible() } else { Enemy::new() };
 
-    spawn_at(world, x, y)
-        .with_physics(6.0)
+    let mut builder = spawn_at(world, x, y)
+        .with_physics(archetype.size)
         .with(AnimationController::new())
         .with(hitstate)
-        .with(Health::new(2))
-        .with(Enemy::new())
-        .with_sprite(sprite_sheet, 7)
-        .with(MeleeEnemy::new(attack_animation, 1))
-        .with(ChaseAndWanderEnemy::new(idle, walking, 50.0, 75.0))
+        .with(Health::new(archetype.health))
+        .with(enemy)
+        .with_sprite(sprite_sheet, archetype.sprite);
+    if let Some(movement) = archetype.movement {
+        let idle = idle_animation(movement.idle);
+        let walking = walking_animation(movement.idle, movement.walk.0, movement.walk.1, movement.walk_duration);
+        builder = builder.with(ChaseAndWanderEnemy::new(idle, walking, movement.wander_speed, movement.chase_speed));
+    }
+    if let Some(melee) = melee {
+        builder = builder.with(melee);
+    }
+    if let Some(ranged) = ranged {
+        builder = builder.with(ranged);
+    }
+    if let Some(behavior) = behavior {
+        builder = builder.with(behavior);
+    }
+    Some(builder)
+}
+
+pub fn spawn_goblin(world: &mut World, x: f32, y: f32) -> EntityBuilder {
+    spawn_enemy(world, "goblin", x, y).expect("No goblin archetype in enemies.ron")
 }
@@ -0,0 +1,154 @@
+use std::fs;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use amethyst::{
+    prelude::*,
+    ecs::*,
+    core::*,
+    core::transform::*,
+    assets::{AssetStorage, Loader, Processor},
+    audio::{AudioData, Source},
+    renderer::{SpriteSheet, Texture, TextureData, TextureMetadata},
+};
+use rayon::ThreadPoolBuilder;
+use crate::ai::*;
+use crate::basics::*;
+use crate::combat::*;
+use crate::drops::*;
+use crate::enemies::EnemyArchetypes;
+use crate::projectiles::ProjectileTypes;
+use crate::boss::{BossHealthPip, Bosses};
+use crate::random::*;
+use crate::replay::*;
+use crate::simulation::*;
+use crate::ui::*;
+use crate::utils::*;
+use crate::world::*;
+
+pub struct Harness {
+    pub world: World,
+    simulation: Dispatcher<'static, 'static>,
+}
+impl Harness {
+    pub fn new(seed: u64) -> Harness {
+        let mut world = World::new();
+        let pool: ArcThreadPool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        world.add_resource(pool.clone());
+        world.add_resource(Loader::new(get_resource(""), pool));
+        world.add_resource(Time::default());
+        world.add_resource(FixedStep::default());
+        world.add_resource(GameRng::new(seed));
+        world.add_resource(PlayerInput::default());
+        world.add_resource(AssetStorage::<Source>::new());
+        world.add_resource(AssetStorage::<Texture>::new());
+        world.add_resource(AssetStorage::<SpriteSheet>::new());
+        world.add_resource(AssetStorage::<HitboxAnimation>::new());
+        world.add_resource(AssetStorage::<BehaviorTree>::new());
+        let layer_config = CollisionLayerConfig::load(get_resource("collision_layers.ron"));
+        world.add_resource(CollisionLayers::from_config(&layer_config)
+            .unwrap_or_else(|err| panic!("Invalid collision_layers.ron: {}", err)));
+        world.register::<GlobalTransform>();
+        world.register::<Parent>();
+        world.register::<UiSprite>();
+        world.register::<StaggerAnimation>();
+        world.register::<Chest>();
+        world.register::<BossHealthPip>();
+        let simulation = build_simulation(&mut world);
+        let main_assets = load_headless_assets(&mut world);
+        world.add_resource(main_assets);
+        init_world(&mut world, (32, 32));
+        Harness { world, simulation }
+    }
+    pub fn step(&mut self, input: PlayerInput) {
+        *self.world.write_resource::<PlayerInput>() = input;
+        self.simulation.dispatch(&self.world.res);
+        self.world.maintain();
+        self.world.write_resource::<FixedStep>().tick += 1;
+    }
+    pub fn run(&mut self, ticks: usize, input: PlayerInput) {
+        for _ in 0..ticks {
+            self.step(input);
+        }
+    }
+    pub fn run_seconds(&mut self, seconds: f32, input: PlayerInput) {
+        let ticks = (seconds / self.world.read_resource::<FixedStep>().delta_seconds()).ceil() as usize;
+        self.run(ticks, input);
+    }
+}
+
+fn load_headless_animation(world: &mut World, name: &str) -> AnimationHandle {
+    let path = get_resource(&format!("animations/{}.ron", name));
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| panic!("Couldn't read {}: {}", path, err));
+    let data: HitboxAnimationData = ron::de::from_str(&text).unwrap_or_else(|err| panic!("Couldn't parse {}: {}", path, err));
+    let loader = world.read_resource::<Loader>();
+    loader.load_from_data(data, (), &world.read_resource::<AssetStorage<HitboxAnimation>>())
+}
+
+fn load_headless_behavior(world: &mut World, name: &str) -> BehaviorHandle {
+    let path = get_resource(&format!("ai/{}.ron", name));
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| panic!("Couldn't read {}: {}", path, err));
+    let tree: BehaviorTree = ron::de::from_str(&text).unwrap_or_else(|err| panic!("Couldn't parse {}: {}", path, err));
+    let loader = world.read_resource::<Loader>();
+    loader.load_from_data(tree, (), &world.read_resource::<AssetStorage<BehaviorTree>>())
+}
+
+fn load_headless_assets(world: &mut World) -> MainAssets {
+    let (sprite_sheet, sound) = {
+        let loader = world.read_resource::<Loader>();
+        let texture = loader.load_from_data(
+            TextureData::Rgba([1.0, 1.0, 1.0, 1.0], TextureMetadata::srgb()),
+            (),
+            &world.read_resource::<AssetStorage<Texture>>(),
+        );
+        let sprite_sheet = loader.load_from_data(
+            SpriteSheet { texture, sprites: Vec::new() },
+            (),
+            &world.read_resource::<AssetStorage<SpriteSheet>>(),
+        );
+        let sound = loader.load_from_data(AudioData(Vec::new()), (), &world.read_resource::<AssetStorage<Source>>());
+        (sprite_sheet, sound)
+    };
+    let archetypes = EnemyArchetypes::load(get_resource("enemies.ron"));
+    let bosses = Bosses::load(get_resource("bosses.ron"));
+    {
+        let layers = world.read_resource::<CollisionLayers>();
+        archetypes.validate(&layers)
+            .and_then(|()| bosses.validate(&archetypes, &layers))
+            .unwrap_or_else(|err| panic!("Invalid enemy data: {}", err));
+    }
+    let mut animation_names = archetypes.animation_names();
+    animation_names.extend(bosses.animation_names());
+    let mut behavior_names = archetypes.behavior_names();
+    behavior_names.extend(bosses.behavior_names());
+    let animations: BTreeMap<_, _> = animation_names.into_iter()
+        .map(|name| {
+            let animation = load_headless_animation(world, &name);
+            (name, animation)
+        })
+        .collect();
+    let behaviors: BTreeMap<_, _> = behavior_names.into_iter()
+        .map(|name| {
+            let behavior = load_headless_behavior(world, &name);
+            (name, behavior)
+        })
+        .collect();
+    world.add_resource(archetypes);
+    world.add_resource(bosses);
+    world.add_resource(ProjectileTypes::load(get_resource("projectiles.ron")));
+    let main_assets = MainAssets {
+        sprite_sheet,
+        swing_sound: sound.clone(),
+        player_hit_sound: sound.clone(),
+        enemy_hit_sound: sound.clone(),
+        purchase_sound: sound,
+        player_attack: load_headless_animation(world, "player_attack"),
+        portal_spin: load_headless_animation(world, "portal_spin"),
+        heart_spin: load_headless_animation(world, "heart_spin"),
+        spend_heart_spin: load_headless_animation(world, "spend_heart_spin"),
+        animations,
+        behaviors,
+    };
+    AnimationProcessor.run_now(&world.res);
+    Processor::<BehaviorTree>::new().run_now(&world.res);
+    main_assets
+}
@@ -0,0 +1,32 @@
+extern crate amethyst;
+#[macro_use]
+extern crate specs_derive;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate rand;
+extern crate rayon;
+extern crate ron;
+extern crate xml;
+pub mod utils;
+pub mod basics;
+pub mod physics;
+pub mod player;
+pub mod combat;
+pub mod enemies;
+pub mod ai;
+pub mod pathfinding;
+pub mod projectiles;
+pub mod boss;
+pub mod dungeon;
+pub mod tiled;
+pub mod sprites;
+pub mod world;
+pub mod drops;
+pub mod ui;
+pub mod sounds;
+pub mod broadphase;
+pub mod simulation;
+pub mod random;
+pub mod replay;
+pub mod harness;
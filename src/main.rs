@@ -1,19 +1,8 @@
 extern crate amethyst;
-#[macro_use]
-extern crate specs_derive;
-extern crate rand;
-mod utils;
-mod basics;
-mod physics;
-mod player;
-mod combat;
-mod enemies;
-mod sprites;
-mod world;
-mod drops;
-mod ui;
+extern crate ludumdare44;
 
 use std::path::Path;
+use std::collections::BTreeMap;
 use amethyst::{
     prelude::*,
     ecs::*,
@@ -24,18 +13,28 @@ use amethyst::{
     assets::*,
     utils::fps_counter::*,
     audio::output::*,
+    shrev::EventChannel,
 };
 use nalgebra::{ Vector3, Point3};
-use crate::utils::*;
-use crate::basics::*;
-use crate::physics::*;
-use crate::player::*;
-use crate::combat::*;
-use crate::enemies::*;
-use crate::sprites::*;
-use crate::world::*;
-use crate::drops::*;
-use crate::ui::*;
+use ludumdare44::utils::*;
+use ludumdare44::basics::*;
+use ludumdare44::physics::*;
+use ludumdare44::player::*;
+use ludumdare44::combat::*;
+use ludumdare44::enemies::*;
+use ludumdare44::ai::*;
+use ludumdare44::projectiles::*;
+use ludumdare44::boss::*;
+use ludumdare44::dungeon::*;
+use ludumdare44::tiled::*;
+use ludumdare44::sprites::*;
+use ludumdare44::world::*;
+use ludumdare44::drops::*;
+use ludumdare44::ui::*;
+use ludumdare44::sounds::*;
+use ludumdare44::simulation::*;
+use ludumdare44::random::*;
+use ludumdare44::replay::*;
 
 struct EmptySystem;
 impl<'s> System<'s> for EmptySystem {
@@ -44,15 +43,40 @@ impl<'s> System<'s> for EmptySystem {
     fn run(&mut self, () : Self::SystemData) {
     }
 }
-struct CameraFollow;
+struct CameraFollow {
+    shake_offset: (f32, f32),
+}
+impl CameraFollow {
+    fn new() -> CameraFollow {
+        CameraFollow { shake_offset: (0.0, 0.0) }
+    }
+}
 impl<'s> System<'s> for CameraFollow {
     type SystemData = (
         WriteStorage<'s, Transform>,
         ReadStorage<'s, Camera>,
         ReadStorage<'s, Player>,
+        Write<'s, ScreenShake>,
+        WriteExpect<'s, GameRng>,
         Read<'s, Time>,
     );
-    fn run(&mut self, (mut transform, camera, player, time) : Self::SystemData) {
+    fn run(&mut self, (mut transform, camera, player, mut shake, mut game_rng, time) : Self::SystemData) {
+        let (old_x, old_y) = self.shake_offset;
+        for (mut transform, camera) in (&mut transform, &camera).join() {
+            transform.translate_x(-old_x);
+            transform.translate_y(-old_y);
+        }
+        self.shake_offset = (0.0, 0.0);
+        if shake.time_left > 0.0 {
+            let rng = game_rng.stream("screen_shake");
+            self.shake_offset = (
+                random_between(rng, -shake.magnitude, shake.magnitude),
+                random_between(rng, -shake.magnitude, shake.magnitude),
+            );
+            shake.time_left -= time.delta_seconds();
+        } else {
+            shake.magnitude = 0.0;
+        }
         let mut target = None;
         let max_slack = 100.0;
         let track_speed = 100.0;
@@ -87,28 +111,36 @@ impl<'s> System<'s> for CameraFollow {
                 }
             }
         }
+        let (x, y) = self.shake_offset;
+        for (mut transform, camera) in (&mut transform, &camera).join() {
+            transform.translate_x(x);
+            transform.translate_y(y);
+        }
     }
 }
 struct DebugDrawHitboxes;
 impl DebugDrawHitboxes {
     fn draw(&self, hitbox: &Hitbox, lines: &mut DebugLines, offset: &Vector3<f32>) {
-        let x = hitbox.offset.0 - hitbox.width / 2.0;
-        let y = hitbox.offset.1 - hitbox.height / 2.0;
-        let width = hitbox.width;
-        let height = hitbox.height;
         let color = hitbox.debug_color.unwrap_or([1., 1., 1., 1.].into());
-        let bottom_left: Point3<f32> = [x, y, 0.1].into();
-        let bottom_left = bottom_left + offset;
-        let bottom_right: Point3<f32> = [x + width, y, 0.1].into();
-        let bottom_right = bottom_right + offset;
-        let top_left: Point3<f32> = [x, y + height, 0.1].into();
-        let top_left = top_left + offset;
-        let top_right: Point3<f32> = [x + width, y + height, 0.1].into();
-        let top_right = top_right + offset;
-        lines.draw_line(bottom_left, bottom_right, color);
-        lines.draw_line(bottom_left, top_left, color);
-        lines.draw_line(top_right, bottom_right, color);
-        lines.draw_line(top_right, top_left, color);
+        let position = (offset.x, offset.y);
+        let points: Vec<Point3<f32>> = match hitbox.shape {
+            HitboxShape::Circle => {
+                let (cx, cy) = hitbox.center(position);
+                let segments = 16;
+                (0..segments).map(|i| {
+                    let theta = i as f32 * std::f32::consts::PI * 2.0 / segments as f32;
+                    [cx + hitbox.radius() * theta.cos(), cy + hitbox.radius() * theta.sin(), offset.z + 0.1].into()
+                }).collect()
+            },
+            HitboxShape::Rectangle | HitboxShape::Oriented(_) => {
+                hitbox.corners(position).iter()
+                    .map(|(x, y)| [*x, *y, offset.z + 0.1].into())
+                    .collect()
+            },
+        };
+        for i in 0..points.len() {
+            lines.draw_line(points[i], points[(i + 1) % points.len()], color);
+        }
     }
 }
 impl<'s> System<'s> for DebugDrawHitboxes {
@@ -135,73 +167,6 @@ impl<'s> System<'s> for DebugDrawHitboxes {
         }
     }
 }
-struct AnimationSystem;
-impl<'s> System<'s> for AnimationSystem {
-    type SystemData = (
-        WriteStorage<'s, AnimationController>,
-        WriteStorage<'s, HitState>,
-        WriteStorage<'s, Velocity>,
-        WriteStorage<'s, SpriteRender>,
-        ReadStorage<'s, Rotation>,
-        Entities<'s>,
-        Read<'s, Time>,
-    );
-    fn run(&mut self, (mut animation, mut hitstate, mut velocity, mut sprite, rotation, entities, time) : Self::SystemData) {
-        for (animation, mut sprite, entity) in (&mut animation, &mut sprite, &entities).join() {
-            let mut hitstate = hitstate.get_mut(entity);
-            if let Some(hitstate) = hitstate.as_mut() {
-                hitstate.clear(ENEMY_ATTACK_BOX);
-                hitstate.clear(PLAYER_ATTACK_BOX);
-            }
-            if animation.active() {
-                let frame = animation.step(time.delta_seconds());
-                if let Some(frame) = frame {
-                    for i in 0..HITSTATE_SIZE {
-                        if let Some(hitstate) = hitstate.as_mut() {
-                            if let Some(hitbox) = frame.hitboxes[i] {
-                                hitstate.set(i, hitbox.width, hitbox.height, hitbox.offset);
-                            }
-                        }
-                    }
-                    if let (Some(frame_velocity), Some(velocity)) = (frame.velocity, velocity.get_mut(entity)) {
-                        let vx;
-                        let vy;
-                        if let Some(rotation) = rotation.get(entity) {
-                            let (rx, ry) = rotation.rotate(frame_velocity);
-                            vx = rx;
-                            vy = ry;
-                        } else {
-                            vx = frame_velocity.0;
-                            vy = frame_velocity.1;
-                        }
-                        velocity.vx = vx;
-                        velocity.vy = vy;
-                    }
-                    if let Some(sprite_id) = frame.sprite {
-                        sprite.sprite_number = sprite_id;
-                    }
-                }
-            }
-        }
-    }
-}
-struct RotationSystem;
-impl<'s> System<'s> for RotationSystem {
-    type SystemData = (
-        ReadStorage<'s, Rotation>,
-        WriteStorage<'s, Transform>,
-        WriteStorage<'s, HitState>,
-    );
-    fn run(&mut self, (rotation, mut transform, mut hitstate) : Self::SystemData) {
-        for (rotation, transform) in (&rotation, &mut transform).join() {
-            rotation.rotate_euler(transform);
-        }
-        for (rotation, hitstate) in (&rotation, &mut hitstate).join() {
-            hitstate.rotate(*rotation);
-        }
-    }
-}
-
 struct InitializingGameState {
     progress: ProgressCounter,
     sprite_sheet: Option<SpriteSheetHandle>,
@@ -216,7 +181,17 @@ impl InitializingGameState {
 }
 impl SimpleState for InitializingGameState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let replay = ReplayMode::from_args(seed_from_args);
+        let seed = replay.seed();
+        println!("Seed: {}", seed);
+        data.world.add_resource(replay);
+        data.world.add_resource(GameRng::new(seed));
         data.world.add_resource(AssetStorage::<amethyst::audio::Source>::new());
+        data.world.add_resource(AssetStorage::<HitboxAnimation>::new());
+        data.world.add_resource(AssetStorage::<BehaviorTree>::new());
+        let layer_config = CollisionLayerConfig::load(get_resource("collision_layers.ron"));
+        data.world.add_resource(CollisionLayers::from_config(&layer_config)
+            .unwrap_or_else(|err| panic!("Invalid collision_layers.ron: {}", err)));
         data.world.add_resource(DebugLines::new().with_capacity(100));
         data.world.add_resource(DebugLinesParams {
             line_width: 100.0,
@@ -229,12 +204,47 @@ impl SimpleState for InitializingGameState {
         let player_hit_sound = load_sound(data.world, get_resource("hit_enemy.wav"), &mut self.progress);
         let enemy_hit_sound = load_sound(data.world, get_resource("hit_player.wav"), &mut self.progress);
         let purchase_sound = load_sound(data.world, get_resource("purchase.wav"), &mut self.progress);
+        let player_attack = load_animation(data.world, get_resource("animations/player_attack.ron"), &mut self.progress);
+        let portal_spin = load_animation(data.world, get_resource("animations/portal_spin.ron"), &mut self.progress);
+        let heart_spin = load_animation(data.world, get_resource("animations/heart_spin.ron"), &mut self.progress);
+        let spend_heart_spin = load_animation(data.world, get_resource("animations/spend_heart_spin.ron"), &mut self.progress);
+        let archetypes = EnemyArchetypes::load(get_resource("enemies.ron"));
+        let bosses = Bosses::load(get_resource("bosses.ron"));
+        {
+            let layers = data.world.read_resource::<CollisionLayers>();
+            archetypes.validate(&layers)
+                .and_then(|()| bosses.validate(&archetypes, &layers))
+                .unwrap_or_else(|err| panic!("Invalid enemy data: {}", err));
+        }
+        let mut animation_names = archetypes.animation_names();
+        animation_names.extend(bosses.animation_names());
+        let mut behavior_names = archetypes.behavior_names();
+        behavior_names.extend(bosses.behavior_names());
+        let mut animations = BTreeMap::new();
+        for name in animation_names {
+            let animation = load_animation(data.world, get_resource(&format!("animations/{}.ron", name)), &mut self.progress);
+            animations.insert(name, animation);
+        }
+        let mut behaviors = BTreeMap::new();
+        for name in behavior_names {
+            let behavior = load_behavior(data.world, get_resource(&format!("ai/{}.ron", name)), &mut self.progress);
+            behaviors.insert(name, behavior);
+        }
+        data.world.add_resource(archetypes);
+        data.world.add_resource(bosses);
+        data.world.add_resource(ProjectileTypes::load(get_resource("projectiles.ron")));
         let main_sprite = MainAssets {
             sprite_sheet: sprite_sheet.clone(),
             swing_sound: swing_sound.clone(),
             player_hit_sound: player_hit_sound.clone(),
             enemy_hit_sound: enemy_hit_sound.clone(),
             purchase_sound: purchase_sound.clone(),
+            player_attack,
+            portal_spin,
+            heart_spin,
+            spend_heart_spin,
+            animations,
+            behaviors,
         };
         data.world.add_resource(main_sprite);
         self.sprite_sheet = Some(sprite_sheet);
@@ -280,10 +290,7 @@ impl SimpleState for GameOverState {
         event: StateEvent,
     ) -> SimpleTrans {
         if want_continue(&event) && data.world.exec(can_continue) {
-            Trans::Switch(Box::new(MainGameState {
-                sprite_sheet: self.sprite_sheet.clone(),
-                player_state: PlayerState::new(),
-            }))
+            Trans::Switch(Box::new(MainGameState::new(self.sprite_sheet.clone(), PlayerState::new())))
         } else {
             Trans::None
         }
@@ -296,7 +303,6 @@ struct TutorialState {
 impl SimpleState for TutorialState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         data.world.delete_all();
-        data.world.read_resource::<Output>().name();
         init_continue(data.world, 0.0, 2.0);
         spawn_at_z(data.world, 0.0, 0.0, 1.0)
             .with(Camera::from(Projection::orthographic(0.0, stage.0, 0.0, stage.1)))
@@ -324,10 +330,7 @@ impl SimpleState for TutorialState {
         event: StateEvent,
     ) -> SimpleTrans {
         if want_continue(&event) && data.world.exec(can_continue) {
-            Trans::Switch(Box::new(MainGameState {
-                sprite_sheet: self.sprite_sheet.clone(),
-                player_state: PlayerState::new(),
-            }))
+            Trans::Switch(Box::new(MainGameState::new(self.sprite_sheet.clone(), PlayerState::new())))
         } else {
             Trans::None
         }
@@ -337,54 +340,94 @@ impl SimpleState for TutorialState {
 struct MainGameState {
     sprite_sheet: SpriteSheetHandle,
     player_state: PlayerState,
+    simulation: Option<Dispatcher<'static, 'static>>,
+}
+impl MainGameState {
+    fn new(sprite_sheet: SpriteSheetHandle, player_state: PlayerState) -> MainGameState {
+        MainGameState {
+            sprite_sheet,
+            player_state,
+            simulation: None,
+        }
+    }
 }
 impl SimpleState for MainGameState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         data.world.delete_all();
-        init_world(data.world);
+        data.world.add_resource(FixedStep::default());
+        if self.player_state.levels == 0 {
+            data.world.write_resource::<GameRng>().reset();
+            data.world.write_resource::<ReplayMode>().restart();
+        }
+        let map_path = if self.player_state.levels == 0 && has_arg("--tutorial") {
+            get_resource("maps/tutorial.tmx")
+        } else {
+            get_resource(&format!("maps/floor_{}.tmx", self.player_state.levels))
+        };
+        let authored = if Path::new(&map_path).exists() {
+            match TiledMap::load(&map_path) {
+                Ok(map) => Some(map),
+                Err(err) => {
+                    println!("Couldn't load {}, generating the floor instead: {}", map_path, err);
+                    None
+                },
+            }
+        } else {
+            None
+        };
         spawn_at_z(data.world, 0.0, 0.0, 1.0)
             .with(Camera::from(Projection::orthographic(0.0, stage.0, 0.0, stage.1)))
             .build();
 
-        spawn_player(data.world, &self.player_state);
-
-        fill_world(data.world, &self.sprite_sheet, 32, 32, FLOOR_EMPTY);
-        draw_wall(data.world, &self.sprite_sheet, (0, 0), (32, 1), WALL);
-        draw_wall(data.world, &self.sprite_sheet, (0, 0), (1, 32), WALL);
-        draw_wall(data.world, &self.sprite_sheet, (31, 0), (1, 32), WALL);
-        draw_wall(data.world, &self.sprite_sheet, (0, 31), (32, 1), WALL);
-
-        spawn_goblin(data.world, 0.0, 0.0)
-            .build();
-
-        spawn_goblin(data.world, stage.0, 0.0)
-            .build();
-
-        portal(data.world, stage.0 / 2.0, stage.1 / 2.0).build();
+        let built = authored.and_then(|map| match build_map(data.world, &self.sprite_sheet, &map) {
+            Ok(start) => Some(start.unwrap_or_else(|| TileMap::tile_center(map.width / 2, map.height / 2))),
+            Err(err) => {
+                println!("Couldn't build {}, generating the floor instead: {}", map_path, err);
+                None
+            },
+        });
+        let player_start = match built {
+            Some(start) => start,
+            None => {
+                let layout = generate(data.world.write_resource::<GameRng>().stream("dungeon"), self.player_state.levels);
+                init_world(data.world, (layout.width, layout.height));
+                draw_dungeon(data.world, &self.sprite_sheet, &layout);
+                let boss = data.world.read_resource::<Bosses>().for_level(self.player_state.levels).map(String::from);
+                populate_dungeon(data.world, &layout, boss.as_ref().map(String::as_str));
+                TileMap::tile_center(layout.player_start.0, layout.player_start.1)
+            },
+        };
+        spawn_player(data.world, &self.player_state, player_start);
 
-        spawn_chest(data.world, 0.0, stage.1, 1, Upgrade::HeartBracelet);
-        spawn_chest(data.world, stage.0, stage.1, 2, Upgrade::GoldenAegis);
+        self.simulation = Some(build_simulation(data.world));
+    }
+    fn on_stop(&mut self, data: StateData<GameData>) {
+        data.world.read_resource::<ReplayMode>().save();
     }
     fn update(&mut self, mut data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
-        let player = data.world.exec(|player: ReadStorage< Player>| {
-            let mut alive = false;
-            for (player) in (&player).join() {
-                alive = true;
+        let frame_seconds = data.world.read_resource::<Time>().delta_seconds();
+        let ticks = data.world.write_resource::<FixedStep>().advance(frame_seconds);
+        for _ in 0..ticks {
+            if let Some(simulation) = self.simulation.as_mut() {
+                simulation.dispatch(&data.world.res);
+            }
+            data.world.maintain();
+            let player = data.world.exec(|player: ReadStorage< Player>| {
+                let mut alive = false;
+                for (player) in (&player).join() {
+                    alive = true;
+                }
+                alive
+            });
+            if data.world.exec(want_advance) {
+                return Trans::Switch(Box::new(MainGameState::new(self.sprite_sheet.clone(), self.player_state.advance(data.world.exec(get_health)))));
+            } else if !player {
+                return Trans::Switch(Box::new(GameOverState {
+                    sprite_sheet: self.sprite_sheet.clone(),
+                }));
             }
-            alive
-        });
-        if data.world.exec(want_advance) {
-            Trans::Switch(Box::new(MainGameState {
-                sprite_sheet: self.sprite_sheet.clone(),
-                player_state: self.player_state.advance(data.world.exec(get_health)),
-            }))
-        } else if player {
-            Trans::None
-        } else {
-            Trans::Switch(Box::new(GameOverState {
-                sprite_sheet: self.sprite_sheet.clone(),
-            }))
         }
+        Trans::None
     }
 }
 
@@ -407,29 +450,19 @@ fn main() -> amethyst::Result<()> {
     let game_data =
         GameDataBuilder::default()
             .with_bundle(input_bundle)?
-            .with_bundle(TransformBundle::new())?
+            .with(InterpolationSystem, "interpolation", &[])
+            .with_bundle(TransformBundle::new().with_dep(&["interpolation"]))?
             .with_bundle(FPSCounterBundle)?
             .with(Processor::<amethyst::audio::Source>::new(), "source_processor", &[])
+            .with(AnimationProcessor, "animation_processor", &[])
+            .with(Processor::<BehaviorTree>::new(), "behavior_processor", &[])
             .with(ContinueSystem, "continue", &[])
-            .with(PlayerMovementSystem::new(), "player_move", &[])
-            .with(ChaseAndWanderSystem, "chase_and_wander", &[])
-            .with(PlayerAttackSystem::new(), "player_attack", &["player_move"])
-            .with(CameraFollow, "camera_follow", &[])
+            .with(CameraFollow::new(), "camera_follow", &["interpolation"])
             .with(UiSpriteSystem, "ui_sprite", &["camera_follow", "transform_system"])
-            .with(RotationSystem, "rotation", &[])
-            .with(AnimationSystem, "animation", &[])
-            .with(VelocitySystem, "velocity", &["animation"])
-            .with(RestitutionSystem, "restitution", &["velocity"])
             .with(DebugDrawHitboxes, "debug_hitboxes", &[])
             .with(PlayerHeartSystem, "hearts", &[])
-            .with(PlayerDamageSystem, "player_damage", &["animation"])
-            .with(EnemyDamageSystem, "enemy_damage", &["animation"])
-            .with(SightSystem, "sight", &["animation"])
-            .with(AimingSystem, "aim", &["sight"])
-            .with(DeathSystem, "death", &["player_damage", "enemy_damage"])
-            .with(PortalSystem, "portal", &[])
-            .with(PurchaseSystem, "purchase", &[])
-            .with(ExitSystem, "exit", &["portal"])
+            .with(BossHealthBarSystem, "boss_health", &[])
+            .with(SoundSystem::new(), "sound", &[])
             .with_barrier()
             .with_bundle(RenderBundle::new(pipe, Some(config))
                 .with_sprite_sheet_processor()
@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use crate::broadphase::Bounds;
+use crate::world::TileMap;
+
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+const MAX_EXPANDED: usize = 2048;
+pub const WAYPOINT_DISTANCE: f32 = 2.0;
+pub const REPLAN_COOLDOWN: f32 = 0.5;
+
+const NEIGHBORS: [(i32, i32); 8] = [
+    ( 1,  0),
+    (-1,  0),
+    ( 0,  1),
+    ( 0, -1),
+    ( 1,  1),
+    ( 1, -1),
+    (-1,  1),
+    (-1, -1),
+];
+
+pub struct NavGrid<'a> {
+    tile_map: &'a TileMap,
+    blocked: HashSet<(i32, i32)>,
+}
+impl<'a> NavGrid<'a> {
+    pub fn new(tile_map: &'a TileMap, obstacles: &[Bounds]) -> NavGrid<'a> {
+        let mut blocked = HashSet::new();
+        for (x0, y0, x1, y1) in obstacles.iter().cloned() {
+            let inset = 0.01;
+            let (tx0, ty0) = TileMap::tile_at((x0 + inset, y0 + inset));
+            let (tx1, ty1) = TileMap::tile_at((x1 - inset, y1 - inset));
+            for x in tx0..=tx1 {
+                for y in ty0..=ty1 {
+                    blocked.insert((x, y));
+                }
+            }
+        }
+        NavGrid { tile_map, blocked }
+    }
+    pub fn in_bounds(&self, (x, y): (i32, i32)) -> bool {
+        x >= 0 && y >= 0 && x < self.tile_map.width() && y < self.tile_map.height()
+    }
+    pub fn walkable(&self, (x, y): (i32, i32)) -> bool {
+        self.in_bounds((x, y)) && !self.tile_map.is_solid(x, y) && !self.blocked.contains(&(x, y))
+    }
+    pub fn find_path(&self, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        if !self.in_bounds(start) || !self.walkable(goal) {
+            return None;
+        }
+        let mut open = BinaryHeap::new();
+        let mut came_from = HashMap::new();
+        let mut cost = HashMap::new();
+        cost.insert(start, 0);
+        open.push(Node { estimate: heuristic(start, goal), cost: 0, tile: start });
+        let mut expanded = 0;
+        while let Some(Node { cost: node_cost, tile, .. }) = open.pop() {
+            if tile == goal {
+                let mut path = vec![tile];
+                let mut current = tile;
+                while let Some(previous) = came_from.get(&current) {
+                    current = *previous;
+                    path.push(current);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if node_cost > cost[&tile] {
+                continue;
+            }
+            expanded += 1;
+            if expanded > MAX_EXPANDED {
+                return None;
+            }
+            for (dx, dy) in NEIGHBORS.iter().cloned() {
+                let next = (tile.0 + dx, tile.1 + dy);
+                if !self.walkable(next) {
+                    continue;
+                }
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal && !(self.walkable((tile.0 + dx, tile.1)) && self.walkable((tile.0, tile.1 + dy))) {
+                    continue;
+                }
+                let next_cost = node_cost + if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                if cost.get(&next).map(|known| next_cost < *known).unwrap_or(true) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, tile);
+                    open.push(Node { estimate: next_cost + heuristic(next, goal), cost: next_cost, tile: next });
+                }
+            }
+        }
+        None
+    }
+}
+
+fn heuristic((ax, ay): (i32, i32), (bx, by): (i32, i32)) -> u32 {
+    let dx = (ax - bx).abs() as u32;
+    let dy = (ay - by).abs() as u32;
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+#[derive(PartialEq, Eq)]
+struct Node {
+    estimate: u32,
+    cost: u32,
+    tile: (i32, i32),
+}
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| other.tile.cmp(&self.tile))
+    }
+}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Path {
+    pub goal: (i32, i32),
+    pub waypoints: Option<VecDeque<(f32, f32)>>,
+    pub retry: f32,
+}
+impl Path {
+    pub fn plan(grid: &NavGrid, from: (f32, f32), to: (f32, f32)) -> Path {
+        let goal = TileMap::tile_at(to);
+        let waypoints = grid.find_path(TileMap::tile_at(from), goal).map(|tiles| {
+            let mut waypoints: VecDeque<_> = tiles.iter().skip(1).map(|(x, y)| TileMap::tile_center(*x, *y)).collect();
+            waypoints.pop_back();
+            waypoints.push_back(to);
+            waypoints
+        });
+        Path { goal, waypoints, retry: REPLAN_COOLDOWN }
+    }
+    pub fn failed(&self) -> bool {
+        self.waypoints.is_none()
+    }
+    pub fn off_course(&self, position: (f32, f32)) -> bool {
+        match self.waypoints.as_ref().and_then(|waypoints| waypoints.front()) {
+            Some(waypoint) => {
+                let (px, py) = TileMap::tile_at(position);
+                let (wx, wy) = TileMap::tile_at(*waypoint);
+                (px - wx).abs().max((py - wy).abs()) > 1
+            },
+            None => false,
+        }
+    }
+    pub fn retarget(&mut self, to: (f32, f32)) {
+        if let Some(last) = self.waypoints.as_mut().and_then(|waypoints| waypoints.back_mut()) {
+            *last = to;
+        }
+    }
+    pub fn next_waypoint(&mut self, position: (f32, f32)) -> Option<(f32, f32)> {
+        let waypoints = self.waypoints.as_mut()?;
+        while let Some((wx, wy)) = waypoints.front().cloned() {
+            if waypoints.len() > 1 && (wx - position.0).hypot(wy - position.1) <= WAYPOINT_DISTANCE {
+                waypoints.pop_front();
+            } else {
+                return Some((wx, wy));
+            }
+        }
+        None
+    }
+}
@@ -5,18 +5,101 @@ use amethyst::{
     core::*,
 };
 use crate::basics::*;
+use crate::simulation::FixedStep;
+use crate::broadphase::*;
+use crate::world::*;
+
+const SLIDE_ITERATIONS: usize = 3;
+const CONTACT_SKIN: f32 = 0.01;
+
+fn slide(bounds: Bounds, (mut dx, mut dy): (f32, f32), obstacles: &Vec<Bounds>) -> (f32, f32) {
+    let mut moved = (0.0, 0.0);
+    for _ in 0..SLIDE_ITERATIONS {
+        let (x0, y0, x1, y1) = bounds;
+        let current = (x0 + moved.0, y0 + moved.1, x1 + moved.0, y1 + moved.1);
+        let mut hit = None;
+        for obstacle in obstacles.iter() {
+            if let Some((time, normal)) = sweep(current, (dx, dy), *obstacle) {
+                match hit {
+                    Some((best, _)) if best <= time => {},
+                    _ => hit = Some((time, normal)),
+                }
+            }
+        }
+        if let Some((time, (nx, ny))) = hit {
+            moved.0 += dx * time + nx * CONTACT_SKIN;
+            moved.1 += dy * time + ny * CONTACT_SKIN;
+            dx *= 1.0 - time;
+            dy *= 1.0 - time;
+            if nx != 0.0 {
+                dx = 0.0;
+            }
+            if ny != 0.0 {
+                dy = 0.0;
+            }
+        } else {
+            moved.0 += dx;
+            moved.1 += dy;
+            return moved;
+        }
+    }
+    moved
+}
+
+pub fn static_obstacles<'a, T, P>(transforms: T, physicals: P) -> Vec<Bounds>
+    where T: Join<Type = &'a Transform>, P: Join<Type = &'a Physical> {
+    let mut obstacles = Vec::new();
+    for (transform, physical) in (transforms, physicals).join() {
+        if physical.is_static {
+            let translation = transform.translation();
+            obstacles.push(physical.hitbox.bounds((translation.x, translation.y)));
+        }
+    }
+    obstacles
+}
+
+pub fn line_of_sight(tile_map: &TileMap, obstacles: &[Bounds], (fx, fy): (f32, f32), (tx, ty): (f32, f32)) -> bool {
+    if tile_map.first_solid_between((fx, fy), (tx, ty)).is_some() {
+        return false;
+    }
+    !obstacles.iter().any(|obstacle| sweep((fx, fy, fx, fy), (tx - fx, ty - fy), *obstacle).is_some())
+}
 
 pub struct VelocitySystem;
 impl<'s> System<'s> for VelocitySystem {
     type SystemData = (
         WriteStorage<'s, Transform>,
         ReadStorage<'s, Velocity>,
-        Read<'s, Time>,
+        ReadStorage<'s, Physical>,
+        Read<'s, TileMap>,
+        Entities<'s>,
+        Read<'s, FixedStep>,
     );
-    fn run(&mut self, (mut transform, velocity, time) : Self::SystemData) {
-        for (mut transform, velocity) in (&mut transform, &velocity).join() {
-            transform.translate_x(velocity.vx * time.delta_seconds());
-            transform.translate_y(velocity.vy * time.delta_seconds());
+    fn run(&mut self, (mut transform, velocity, physical, tile_map, entities, time) : Self::SystemData) {
+        let obstacles = static_obstacles(&transform, &physical);
+        for (mut transform, velocity, entity) in (&mut transform, &velocity, &entities).join() {
+            let movement = (velocity.vx * time.delta_seconds(), velocity.vy * time.delta_seconds());
+            let (dx, dy) = match physical.get(entity) {
+                Some(physical) if !physical.is_static => {
+                    let translation = transform.translation();
+                    let bounds = physical.hitbox.bounds((translation.x, translation.y));
+                    let (x0, y0, x1, y1) = bounds;
+                    let swept = (
+                        x0.min(x0 + movement.0),
+                        y0.min(y0 + movement.1),
+                        x1.max(x1 + movement.0),
+                        y1.max(y1 + movement.1),
+                    );
+                    let mut nearby = obstacles.clone();
+                    for (tx, ty) in tile_map.solid_tiles_in(swept) {
+                        nearby.push(TileMap::tile_bounds(tx, ty));
+                    }
+                    slide(bounds, movement, &nearby)
+                },
+                _ => movement,
+            };
+            transform.translate_x(dx);
+            transform.translate_y(dy);
         }
     }
 }
@@ -26,9 +109,14 @@ impl<'s> System<'s> for RestitutionSystem {
     type SystemData = (
         WriteStorage<'s, Transform>,
         ReadStorage<'s, Physical>,
+        Read<'s, TileMap>,
         Entities<'s>,
     );
-    fn run(&mut self, (mut transform, physical, entities) : Self::SystemData) {
+    fn run(&mut self, (mut transform, physical, tile_map, entities) : Self::SystemData) {
+        let tile = Physical {
+            hitbox: Hitbox::new_at_rect(TILE_SIZE.0 as f32, TILE_SIZE.1 as f32, (0.0, 0.0)),
+            is_static: true,
+        };
         let mut obstacles = Vec::new();
         for (transform, physical, entity) in (&transform, &physical, &entities).join() {
             obstacles.push((transform.translation().clone(), physical, entity));
@@ -59,8 +147,21 @@ impl<'s> System<'s> for RestitutionSystem {
                     }
                 }
             }
-            transform.translate_x(restitution.0);
-            transform.translate_y(restitution.1);
+            let mut tile_push: (f32, f32) = (0.0, 0.0);
+            for (tx, ty) in tile_map.solid_tiles_in(physical.hitbox.bounds((x, y))) {
+                let (cx, cy) = TileMap::tile_center(tx, ty);
+                if let Some((_, _, dx, dy)) = physical.depth(&tile, (x, y), (cx, cy)) {
+                    if dy.abs() > dx.abs() {
+                        if dx.abs() > tile_push.0.abs() {
+                            tile_push.0 = dx * (x - cx).signum();
+                        }
+                    } else if dy.abs() > tile_push.1.abs() {
+                        tile_push.1 = dy * (y - cy).signum();
+                    }
+                }
+            }
+            transform.translate_x(restitution.0 + tile_push.0);
+            transform.translate_y(restitution.1 + tile_push.1);
         }
     }
 }
@@ -4,9 +4,12 @@ use amethyst::{
     renderer::*,
     core::*,
     input::*,
+    assets::AssetStorage,
 };
 use crate::sprites::*;
 use crate::basics::*;
+use crate::replay::PlayerInput;
+use crate::simulation::FixedStep;
 use crate::combat::*;
 use crate::ui::*;
 use crate::utils::*;
@@ -195,142 +198,108 @@ impl<'s> System<'s> for PlayerMovementSystem {
         WriteStorage<'s, Velocity>,
         WriteStorage<'s, Rotation>,
         WriteStorage<'s, AnimationController>,
-        Read<'s, InputHandler<String, String>>,
-        Read<'s, Time>,
+        Read<'s, PlayerInput>,
+        Read<'s, FixedStep>,
         Entities<'s>,
     );
     fn run(&mut self, (players, mut velocities, mut rotations, mut animations, input, time, entities) : Self::SystemData) {
         let deacc_factor = 3.0;
         for (player, mut velocity, mut animation, entity) in (&players, &mut velocities, &mut animations, &entities).join() {
-            if animation.state() != AnimationState::Idle && animation.state() != AnimationState::Walking {
+            if !animation.can_interrupt(AnimationState::Walking) {
                 continue;
             }
-            let x_tilt = input.axis_value("leftright");
-            let y_tilt = input.axis_value("updown");
-            if let (Some(x_tilt), Some(y_tilt)) = (x_tilt, y_tilt) {
-                let mut x_accel = 0.0;
-                if x_tilt < 0.0 {
-                    x_accel = -player.walk_accel;
-                } else if x_tilt > 0.0 {
-                    x_accel = player.walk_accel;
-                } else if velocity.vx != 0.0 {
-                    let direction = velocity.vx / velocity.vx.abs();
-                    x_accel = player.walk_accel * -direction;
-                }
-                let mut y_accel = 0.0;
-                if y_tilt < 0.0 {
-                    y_accel = -player.walk_accel;
-                } else if y_tilt > 0.0 {
-                    y_accel = player.walk_accel;
-                } else if velocity.vy != 0.0 {
-                    let direction = velocity.vy / velocity.vy.abs();
-                    y_accel = player.walk_accel * -direction;
-                }
-                if (x_accel > 0.0 && velocity.vx <= 0.0)
-                    || (x_accel < 0.0 && velocity.vx > 0.0){
-                    x_accel *= deacc_factor;
-                }
-                if (y_accel > 0.0 && velocity.vy <= 0.0)
-                    || (y_accel < 0.0 && velocity.vy > 0.0){
-                    y_accel *= deacc_factor;
-                }
-                if x_tilt == 0.0 && x_accel.abs() * time.delta_seconds() > velocity.vx.abs() {
-                    velocity.vx = 0.0;
-                } else {
-                    velocity.vx += x_accel * time.delta_seconds();
-                }
-                if y_tilt == 0.0 && y_accel.abs() * time.delta_seconds() > velocity.vy.abs() {
-                    velocity.vy = 0.0;
-                } else {
-                    velocity.vy += y_accel * time.delta_seconds();
-                }
-                if velocity.vx.abs() > player.walk_speed {
-                    velocity.vx = player.walk_speed * velocity.vx.signum();
-                }
-                if velocity.vy.abs() > player.walk_speed {
-                    velocity.vy = player.walk_speed * velocity.vy.signum();
-                }
-                if x_tilt != 0.0 || y_tilt != 0.0 {
-                    let rotation;
-                    if velocity.vx.abs() > velocity.vy.abs() {
-                        if velocity.vx < 0.0 {
-                            rotation = Some(Rotation::West);
-                        } else {
-                            rotation = Some(Rotation::East);
-                        }
-                    } else {
-                        if velocity.vy < 0.0 {
-                            rotation = Some(Rotation::South);
-                        } else {
-                            rotation = Some(Rotation::North);
-                        }
-                    }
-                    if let Some(rotation) = rotation {
-                        if let Ok(_) = rotations.insert(entity, rotation) {
+            let x_tilt = input.leftright;
+            let y_tilt = input.updown;
+            let mut x_accel = 0.0;
+            if x_tilt < 0.0 {
+                x_accel = -player.walk_accel;
+            } else if x_tilt > 0.0 {
+                x_accel = player.walk_accel;
+            } else if velocity.vx != 0.0 {
+                let direction = velocity.vx / velocity.vx.abs();
+                x_accel = player.walk_accel * -direction;
+            }
+            let mut y_accel = 0.0;
+            if y_tilt < 0.0 {
+                y_accel = -player.walk_accel;
+            } else if y_tilt > 0.0 {
+                y_accel = player.walk_accel;
+            } else if velocity.vy != 0.0 {
+                let direction = velocity.vy / velocity.vy.abs();
+                y_accel = player.walk_accel * -direction;
+            }
+            if (x_accel > 0.0 && velocity.vx <= 0.0)
+                || (x_accel < 0.0 && velocity.vx > 0.0){
+                x_accel *= deacc_factor;
+            }
+            if (y_accel > 0.0 && velocity.vy <= 0.0)
+                || (y_accel < 0.0 && velocity.vy > 0.0){
+                y_accel *= deacc_factor;
+            }
+            if x_tilt == 0.0 && x_accel.abs() * time.delta_seconds() > velocity.vx.abs() {
+                velocity.vx = 0.0;
+            } else {
+                velocity.vx += x_accel * time.delta_seconds();
+            }
+            if y_tilt == 0.0 && y_accel.abs() * time.delta_seconds() > velocity.vy.abs() {
+                velocity.vy = 0.0;
+            } else {
+                velocity.vy += y_accel * time.delta_seconds();
+            }
+            if velocity.vx.abs() > player.walk_speed {
+                velocity.vx = player.walk_speed * velocity.vx.signum();
+            }
+            if velocity.vy.abs() > player.walk_speed {
+                velocity.vy = player.walk_speed * velocity.vy.signum();
+            }
+            if x_tilt != 0.0 || y_tilt != 0.0 {
+                let rotation = Some(Rotation::toward(velocity.vx, velocity.vy).snapped(8));
+                if let Some(rotation) = rotation {
+                    if let Ok(_) = rotations.insert(entity, rotation) {
 
-                        }
-                    }
-                    if animation.state() == AnimationState::Idle {
-                        animation.start(self.walking.clone(), AnimationState::Walking);
-                    }
-                } else {
-                    if animation.state() == AnimationState::Walking || !animation.active() {
-                        animation.start(self.idle.clone(), AnimationState::Idle);
                     }
                 }
+                animation.start(self.walking.clone(), AnimationState::Walking);
+            } else {
+                animation.start(self.idle.clone(), AnimationState::Idle);
             }
         }
     }
 }
 
-pub struct PlayerAttackSystem {
-    attacks: Vec<HitboxAnimation>,
-}
-impl PlayerAttackSystem {
-    pub fn new() -> PlayerAttackSystem {
-        let mut attacks = Vec::new();
-        let mut base_attack = HitboxAnimation::new();
-        let frame = base_attack.add_frame_with_velocity((0.0, 0.0), 0.1);
-        base_attack.set_sprite(frame, PLAYER_ATTACK_0);
-        let frame = base_attack.add_frame_with_velocity((0.0, 0.0), 0.2);
-        base_attack.set_hitbox(frame, PLAYER_ATTACK_BOX, Hitbox::new_at(8.0, (8.0, 0.0)));
-        base_attack.set_sprite(frame, PLAYER_ATTACK_1);
-        let frame = base_attack.add_frame_with_velocity((0.0, 0.0), 0.2);
-        base_attack.set_hitbox(frame, PLAYER_ATTACK_BOX, Hitbox::new_at(4.0, (10.0, 0.0)));
-        base_attack.set_sprite(frame, PLAYER_ATTACK_2);
-        attacks.push(base_attack);
-        PlayerAttackSystem {
-            attacks,
-        }
-    }
-}
+pub struct PlayerAttackSystem;
 impl<'s> System<'s> for PlayerAttackSystem {
     type SystemData = (
         ReadStorage<'s, Player>,
         WriteStorage<'s, AnimationController>,
         WriteStorage<'s, HitState>,
-        Read<'s, InputHandler<String, String>>,
+        Read<'s, PlayerInput>,
+        ReadExpect<'s, MainAssets>,
+        Read<'s, AssetStorage<HitboxAnimation>>,
+        Read<'s, CollisionLayers>,
     );
-    fn run(&mut self, (player, mut animation_controller, mut hitstate, input) : Self::SystemData) {
-        if let Some(true) = input.action_is_down("attack") {
+    fn run(&mut self, (player, mut animation_controller, mut hitstate, input, main_assets, animations, layers) : Self::SystemData) {
+        if input.attack {
             for (player, mut animation_controller) in (&player, &mut animation_controller).join() {
-                if animation_controller.state() == AnimationState::Idle || animation_controller.state() == AnimationState::Walking {
-                    animation_controller.start(self.attacks[0].clone(), AnimationState::Attacking);
-                }
+                animation_controller.queue(get_animation(&animations, &main_assets.player_attack), AnimationState::Attacking);
             }
         }
+        let attack_layer = layers.named("player_attack");
         for (player, mut hitstate, animation_controller) in (&player, &mut hitstate, &animation_controller).join() {
             if animation_controller.state() != AnimationState::Attacking {
-                hitstate.clear(PLAYER_ATTACK_BOX);
+                hitstate.clear(attack_layer);
             }
         }
     }
 }
 
-pub fn spawn_player(world: &mut World, player_state: &PlayerState) {
+pub fn spawn_player(world: &mut World, player_state: &PlayerState, (x, y): (f32, f32)) {
     let mut hitboxes = HitState::new();
-    hitboxes.set(ENEMY_HITTABLE_BOX, 16.0, 16.0, (0.0, 0.0));
-    hitboxes.set(PLAYER_INTERACT_BOX, 24.0, 16.0, (8.0, 0.0));
+    {
+        let layers = world.read_resource::<CollisionLayers>();
+        hitboxes.set(layers.named("enemy_hittable"), 16.0, 16.0, (0.0, 0.0));
+        hitboxes.set(layers.named("player_interact"), 24.0, 16.0, (8.0, 0.0));
+    }
     let hearts = [
         draw_sprite(world, FULL_HEART, Anchor::TopLeft, (0.0, 0.0)).build(),
         draw_sprite(world, FULL_HEART, Anchor::TopLeft, (16.0, 0.0)).build(),
@@ -344,7 +313,7 @@ pub fn spawn_player(world: &mut World, player_state: &PlayerState) {
         draw_sprite(world, FULL_HEART, Anchor::TopLeft, (144.0, 0.0)).build(),
     ];
     let sprite_sheet = get_sprite_sheet(world);
-    spawn_at(world, stage.0 / 2.0, stage.1 / 2.0)
+    spawn_at(world, x, y)
         .with_sprite(sprite_sheet, PLAYER_IDLE)
         .with(Player::new(hearts, player_state.upgrades.clone()))
         .with(hitboxes)
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use amethyst::{
+    prelude::*,
+    ecs::*,
+    core::transform::*,
+    renderer::{SpriteRender, Transparent},
+    shrev::{EventChannel, ReaderId},
+};
+use crate::basics::*;
+use crate::physics::static_obstacles;
+use crate::player::Player;
+use crate::simulation::{FixedStep, Interpolation};
+use crate::utils::*;
+use crate::world::TileMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Faction {
+    Player,
+    Enemy,
+}
+impl Faction {
+    pub fn attack_layer_name(&self) -> &'static str {
+        match self {
+            Faction::Player => "player_attack",
+            Faction::Enemy => "enemy_attack",
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Projectile {
+    pub faction: Faction,
+    pub layer: Layer,
+    pub damage: i32,
+    pub lifetime: f32,
+    pub spent: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectileData {
+    pub sprite: usize,
+    pub speed: f32,
+    pub size: f32,
+    pub damage: i32,
+    pub lifetime: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProjectileTypes {
+    #[serde(default)]
+    pub projectiles: BTreeMap<String, ProjectileData>,
+}
+
+pub struct ProjectileSpawnSystem {
+    reader: Option<ReaderId<AnimationEvent>>,
+}
+impl ProjectileSpawnSystem {
+    pub fn new() -> ProjectileSpawnSystem {
+        ProjectileSpawnSystem { reader: None }
+    }
+}
+impl<'s> System<'s> for ProjectileSpawnSystem {
+    type SystemData = (
+        Read<'s, EventChannel<AnimationEvent>>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Rotation>,
+        ReadStorage<'s, Player>,
+        Read<'s, ProjectileTypes>,
+        Read<'s, CollisionLayers>,
+        ReadExpect<'s, MainAssets>,
+        Read<'s, LazyUpdate>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (events, transforms, rotations, players, types, layers, main_assets, lazy, entities) : Self::SystemData) {
+        for animation_event in events.read(self.reader.as_mut().unwrap()) {
+            if let FrameEvent::Spawn { ref name, offset } = animation_event.event {
+                let owner = animation_event.entity;
+                let (data, transform) = match (types.projectiles.get(name), transforms.get(owner)) {
+                    (Some(data), Some(transform)) => (data, transform),
+                    _ => continue,
+                };
+                let rotation = rotations.get(owner).cloned().unwrap_or(Rotation::EAST);
+                let faction = if players.contains(owner) { Faction::Player } else { Faction::Enemy };
+                let layer = layers.named(faction.attack_layer_name());
+                let (ox, oy) = rotation.rotate(offset);
+                let (vx, vy) = rotation.rotate((data.speed, 0.0));
+                let mut hitstate = HitState::new();
+                hitstate.set(layer, data.size * 2.0, data.size * 2.0, (0.0, 0.0));
+                let projectile = entities.create();
+                lazy.insert(projectile, at(transform.translation().x + ox, transform.translation().y + oy));
+                lazy.insert(projectile, GlobalTransform::default());
+                lazy.insert(projectile, SpriteRender {
+                    sprite_sheet: main_assets.sprite_sheet.clone(),
+                    sprite_number: data.sprite,
+                });
+                lazy.insert(projectile, Transparent);
+                lazy.insert(projectile, Velocity { vx, vy });
+                lazy.insert(projectile, rotation);
+                lazy.insert(projectile, hitstate);
+                lazy.insert(projectile, Interpolation::new());
+                lazy.insert(projectile, Projectile {
+                    faction,
+                    layer,
+                    damage: data.damage,
+                    lifetime: data.lifetime,
+                    spent: false,
+                });
+            }
+        }
+    }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<AnimationEvent>>().register_reader());
+    }
+}
+
+pub struct ProjectileSystem;
+impl<'s> System<'s> for ProjectileSystem {
+    type SystemData = (
+        WriteStorage<'s, Projectile>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Physical>,
+        ReadStorage<'s, HitState>,
+        Read<'s, TileMap>,
+        Read<'s, FixedStep>,
+        Entities<'s>,
+    );
+    fn run(&mut self, (mut projectiles, transforms, physicals, hitstates, tile_map, time, entities) : Self::SystemData) {
+        let obstacles = static_obstacles(&transforms, &physicals);
+        for (projectile, transform, hitstate, entity) in (&mut projectiles, &transforms, &hitstates, &entities).join() {
+            projectile.lifetime -= time.delta_seconds();
+            let position = (transform.translation().x, transform.translation().y);
+            let hit_wall = match hitstate.get(projectile.layer) {
+                Some(hitbox) => {
+                    let (x0, y0, x1, y1) = hitbox.bounds(position);
+                    !tile_map.solid_tiles_in((x0, y0, x1, y1)).is_empty()
+                        || obstacles.iter().any(|&(ox0, oy0, ox1, oy1)| x0 < ox1 && ox0 < x1 && y0 < oy1 && oy0 < y1)
+                },
+                None => false,
+            };
+            if projectile.spent || projectile.lifetime <= 0.0 || hit_wall {
+                let _ = entities.delete(entity);
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use crate::utils::arg_value;
+
+fn fnv1a(name: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn seed_from_args() -> u64 {
+    if let Some(value) = arg_value("--seed") {
+        match value.parse() {
+            Ok(seed) => return seed,
+            Err(_) => println!("Ignoring invalid seed {}", value),
+        }
+    }
+    thread_rng().gen()
+}
+
+pub struct GameRng {
+    seed: u64,
+    streams: HashMap<String, StdRng>,
+}
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng {
+            seed,
+            streams: HashMap::new(),
+        }
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn reset(&mut self) {
+        self.streams.clear();
+    }
+    pub fn stream(&mut self, name: &str) -> &mut StdRng {
+        let seed = self.seed ^ fnv1a(name);
+        self.streams.entry(name.to_string()).or_insert_with(|| StdRng::seed_from_u64(seed))
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs;
+use amethyst::{
+    ecs::*,
+    input::*,
+    utils::application_root_dir,
+};
+use ron::ser::{to_string_pretty, PrettyConfig};
+use crate::utils::{arg_value, has_arg};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub leftright: f64,
+    pub updown: f64,
+    pub attack: bool,
+    pub interact: bool,
+}
+impl PlayerInput {
+    pub fn from_handler(input: &InputHandler<String, String>) -> PlayerInput {
+        PlayerInput {
+            leftright: input.axis_value("leftright").unwrap_or(0.0),
+            updown: input.axis_value("updown").unwrap_or(0.0),
+            attack: input.action_is_down("attack") == Some(true),
+            interact: input.action_is_down("interact") == Some(true),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub inputs: Vec<(u32, PlayerInput)>,
+}
+impl Replay {
+    pub fn new(seed: u64) -> Replay {
+        Replay {
+            seed,
+            inputs: Vec::new(),
+        }
+    }
+    pub fn push(&mut self, input: PlayerInput) {
+        match self.inputs.last_mut() {
+            Some((count, last)) if *last == input => *count += 1,
+            _ => self.inputs.push((1, input)),
+        }
+    }
+    pub fn ticks(&self) -> u32 {
+        self.inputs.iter().map(|(count, _)| count).sum()
+    }
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Couldn't read replay {}: {}", path, err))?;
+        ron::de::from_str(&text).map_err(|err| format!("Couldn't parse replay {}: {}", path, err))
+    }
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = to_string_pretty(self, PrettyConfig::default()).map_err(|err| format!("Couldn't encode replay: {}", err))?;
+        fs::write(path, text).map_err(|err| format!("Couldn't write replay {}: {}", path, err))
+    }
+}
+
+pub enum ReplayMode {
+    Live {
+        seed: u64,
+    },
+    Recording {
+        replay: Replay,
+        path: String,
+    },
+    Playing {
+        replay: Replay,
+        run: usize,
+        used: u32,
+    },
+}
+impl ReplayMode {
+    pub fn from_args(seed: impl FnOnce() -> u64) -> ReplayMode {
+        if let Some(path) = arg_value("--replay") {
+            match Replay::load(&path) {
+                Ok(replay) => {
+                    println!("Playing replay {} ({} ticks)", path, replay.ticks());
+                    return ReplayMode::Playing { replay, run: 0, used: 0 };
+                },
+                Err(err) => println!("{}", err),
+            }
+        }
+        if !has_arg("--record") {
+            return ReplayMode::Live { seed: seed() };
+        }
+        let path = arg_value("--record")
+            .filter(|path| !path.starts_with("--"))
+            .unwrap_or_else(|| format!("{}/replay.ron", application_root_dir()));
+        ReplayMode::Recording {
+            replay: Replay::new(seed()),
+            path,
+        }
+    }
+    pub fn seed(&self) -> u64 {
+        match self {
+            ReplayMode::Live { seed } => *seed,
+            ReplayMode::Recording { replay, .. } => replay.seed,
+            ReplayMode::Playing { replay, .. } => replay.seed,
+        }
+    }
+    pub fn restart(&mut self) {
+        match self {
+            ReplayMode::Live { .. } => {},
+            ReplayMode::Recording { replay, .. } => replay.inputs.clear(),
+            ReplayMode::Playing { run, used, .. } => {
+                *run = 0;
+                *used = 0;
+            },
+        }
+    }
+    pub fn next(&mut self, live: PlayerInput) -> PlayerInput {
+        match self {
+            ReplayMode::Live { .. } => live,
+            ReplayMode::Recording { replay, .. } => {
+                replay.push(live);
+                live
+            },
+            ReplayMode::Playing { replay, run, used } => {
+                while let Some((count, input)) = replay.inputs.get(*run) {
+                    if *used < *count {
+                        *used += 1;
+                        return *input;
+                    }
+                    *run += 1;
+                    *used = 0;
+                    if *run == replay.inputs.len() {
+                        println!("Replay finished after {} ticks", replay.ticks());
+                    }
+                }
+                PlayerInput::default()
+            },
+        }
+    }
+    pub fn save(&self) {
+        if let ReplayMode::Recording { replay, path } = self {
+            match replay.save(path) {
+                Ok(()) => println!("Saved replay to {}", path),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+}
+
+pub struct InputCaptureSystem;
+impl<'s> System<'s> for InputCaptureSystem {
+    type SystemData = (
+        Read<'s, InputHandler<String, String>>,
+        Write<'s, PlayerInput>,
+        Option<Write<'s, ReplayMode>>,
+    );
+    fn run(&mut self, (input, mut player_input, replay) : Self::SystemData) {
+        if let Some(mut replay) = replay {
+            *player_input = replay.next(PlayerInput::from_handler(&input));
+        }
+    }
+}
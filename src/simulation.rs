@@ -0,0 +1,228 @@
+use amethyst::{
+    prelude::*,
+    ecs::*,
+    core::*,
+    core::transform::*,
+    renderer::SpriteRender,
+    shrev::EventChannel,
+};
+use crate::basics::*;
+use crate::physics::*;
+use crate::player::*;
+use crate::combat::*;
+use crate::enemies::*;
+use crate::ai::*;
+use crate::projectiles::*;
+use crate::boss::*;
+use crate::world::*;
+use crate::drops::*;
+use crate::replay::*;
+
+const MAX_TICKS_PER_FRAME: usize = 5;
+
+pub struct FixedStep {
+    pub step: f32,
+    pub alpha: f32,
+    pub tick: u64,
+    accumulator: f32,
+}
+impl Default for FixedStep {
+    fn default() -> FixedStep {
+        FixedStep::new(1.0 / 60.0)
+    }
+}
+impl FixedStep {
+    pub fn new(step: f32) -> FixedStep {
+        FixedStep {
+            step,
+            alpha: 0.0,
+            tick: 0,
+            accumulator: 0.0,
+        }
+    }
+    pub fn delta_seconds(&self) -> f32 {
+        self.step
+    }
+    pub fn advance(&mut self, frame_seconds: f32) -> usize {
+        self.accumulator += frame_seconds;
+        let mut ticks = 0;
+        while self.accumulator >= self.step && ticks < MAX_TICKS_PER_FRAME {
+            self.accumulator -= self.step;
+            ticks += 1;
+        }
+        if ticks == MAX_TICKS_PER_FRAME {
+            self.accumulator = self.accumulator.min(self.step);
+        }
+        self.tick += ticks as u64;
+        self.alpha = self.accumulator / self.step;
+        ticks
+    }
+}
+
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct Interpolation {
+    previous: Option<(f32, f32)>,
+    current: Option<(f32, f32)>,
+}
+impl Interpolation {
+    pub fn new() -> Interpolation {
+        Interpolation {
+            previous: None,
+            current: None,
+        }
+    }
+}
+
+pub struct RestoreSimulationSystem;
+impl<'s> System<'s> for RestoreSimulationSystem {
+    type SystemData = (
+        ReadStorage<'s, Interpolation>,
+        WriteStorage<'s, Transform>,
+    );
+    fn run(&mut self, (interpolation, mut transform) : Self::SystemData) {
+        for (interpolation, transform) in (&interpolation, &mut transform).join() {
+            if let Some((x, y)) = interpolation.current {
+                transform.set_x(x);
+                transform.set_y(y);
+            }
+        }
+    }
+}
+
+pub struct CaptureSimulationSystem;
+impl<'s> System<'s> for CaptureSimulationSystem {
+    type SystemData = (
+        WriteStorage<'s, Interpolation>,
+        ReadStorage<'s, Transform>,
+    );
+    fn run(&mut self, (mut interpolation, transform) : Self::SystemData) {
+        for (interpolation, transform) in (&mut interpolation, &transform).join() {
+            let position = (transform.translation().x, transform.translation().y);
+            interpolation.previous = Some(interpolation.current.unwrap_or(position));
+            interpolation.current = Some(position);
+        }
+    }
+}
+
+pub struct InterpolationSystem;
+impl<'s> System<'s> for InterpolationSystem {
+    type SystemData = (
+        ReadStorage<'s, Interpolation>,
+        WriteStorage<'s, Transform>,
+        Read<'s, FixedStep>,
+    );
+    fn run(&mut self, (interpolation, mut transform, fixed_step) : Self::SystemData) {
+        for (interpolation, transform) in (&interpolation, &mut transform).join() {
+            if let (Some((px, py)), Some((cx, cy))) = (interpolation.previous, interpolation.current) {
+                transform.set_x(px + (cx - px) * fixed_step.alpha);
+                transform.set_y(py + (cy - py) * fixed_step.alpha);
+            }
+        }
+    }
+}
+
+pub struct AnimationSystem;
+impl<'s> System<'s> for AnimationSystem {
+    type SystemData = (
+        WriteStorage<'s, AnimationController>,
+        WriteStorage<'s, HitState>,
+        WriteStorage<'s, Velocity>,
+        WriteStorage<'s, SpriteRender>,
+        ReadStorage<'s, Rotation>,
+        Write<'s, EventChannel<AnimationEvent>>,
+        Entities<'s>,
+        Read<'s, FixedStep>,
+    );
+    fn run(&mut self, (mut animation, mut hitstate, mut velocity, mut sprite, rotation, mut events, entities, time) : Self::SystemData) {
+        for (animation, mut sprite, entity) in (&mut animation, &mut sprite, &entities).join() {
+            let mut hitstate = hitstate.get_mut(entity);
+            if let Some(hitstate) = hitstate.as_mut() {
+                hitstate.clear_animated();
+            }
+            if animation.active() {
+                let frame = animation.step(time.delta_seconds());
+                if let Some(frame) = frame {
+                    if let Some(hitstate) = hitstate.as_mut() {
+                        for (layer, hitbox) in frame.hitboxes.iter() {
+                            hitstate.set_animated(*layer, *hitbox);
+                        }
+                    }
+                    if let (Some(frame_velocity), Some(velocity)) = (frame.velocity, velocity.get_mut(entity)) {
+                        let vx;
+                        let vy;
+                        if let Some(rotation) = rotation.get(entity) {
+                            let (rx, ry) = rotation.rotate(frame_velocity);
+                            vx = rx;
+                            vy = ry;
+                        } else {
+                            vx = frame_velocity.0;
+                            vy = frame_velocity.1;
+                        }
+                        velocity.vx = vx;
+                        velocity.vy = vy;
+                    }
+                    if let Some(sprite_id) = frame.sprite {
+                        sprite.sprite_number = sprite_id;
+                    }
+                    for event in animation.take_events() {
+                        events.single_write(AnimationEvent { entity, event });
+                    }
+                }
+            }
+        }
+    }
+}
+pub struct RotationSystem;
+impl<'s> System<'s> for RotationSystem {
+    type SystemData = (
+        ReadStorage<'s, Rotation>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, HitState>,
+    );
+    fn run(&mut self, (rotation, mut transform, mut hitstate) : Self::SystemData) {
+        for (rotation, transform) in (&rotation, &mut transform).join() {
+            rotation.rotate_euler(transform);
+        }
+        for (rotation, hitstate) in (&rotation, &mut hitstate).join() {
+            hitstate.rotate(*rotation);
+        }
+    }
+}
+
+pub fn build_simulation(world: &mut World) -> Dispatcher<'static, 'static> {
+    let mut builder = DispatcherBuilder::new();
+    if world.res.has_value::<ArcThreadPool>() {
+        builder = builder.with_pool(world.read_resource::<ArcThreadPool>().clone());
+    }
+    let mut dispatcher = builder
+        .with(RestoreSimulationSystem, "restore", &[])
+        .with(InputCaptureSystem, "input_capture", &[])
+        .with(PlayerMovementSystem::new(), "player_move", &["restore", "input_capture"])
+        .with(BehaviorSystem, "behavior", &["restore"])
+        .with(PlayerAttackSystem, "player_attack", &["player_move"])
+        .with(RotationSystem, "rotation", &["restore"])
+        .with(AnimationSystem, "animation", &["player_attack", "behavior"])
+        .with(VelocitySystem, "velocity", &["animation"])
+        .with(RestitutionSystem, "restitution", &["velocity"])
+        .with(ProjectileSystem, "projectiles", &["velocity"])
+        .with(BroadphaseSystem, "broadphase", &["animation", "rotation", "restitution", "projectiles"])
+        .with(CollisionSystem::new(), "collision", &["broadphase"])
+        .with(PlayerDamageSystem::new(), "player_damage", &["collision"])
+        .with(EnemyDamageSystem::new(), "enemy_damage", &["collision"])
+        .with(SightSystem::new(), "sight", &["collision"])
+        .with(AimingSystem::new(), "aim", &["sight"])
+        .with(DeathSystem, "death", &["player_damage", "enemy_damage"])
+        .with(BossPhaseSystem, "boss_phase", &["player_damage", "behavior"])
+        .with(FrameEventSystem::new(), "frame_events", &["animation"])
+        .with(ProjectileSpawnSystem::new(), "projectile_spawn", &["animation"])
+        .with(InvulnerabilityBlinkSystem, "invulnerability_blink", &["death", "frame_events"])
+        .with(PortalSystem, "portal", &[])
+        .with(PurchaseSystem::new(), "purchase", &["collision", "input_capture"])
+        .with(ExitSystem::new(), "exit", &["portal", "collision"])
+        .with_barrier()
+        .with(CaptureSimulationSystem, "capture", &[])
+        .build();
+    dispatcher.setup(&mut world.res);
+    dispatcher
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use amethyst::{
+    prelude::*,
+    ecs::*,
+    core::*,
+    assets::AssetStorage,
+    audio::{Source, SourceHandle, output::Output},
+    shrev::{EventChannel, ReaderId},
+};
+use crate::basics::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Sound {
+    Swing,
+    HitEnemy,
+    HitPlayer,
+    Purchase,
+}
+impl Sound {
+    fn cooldown(&self) -> f32 {
+        match self {
+            Sound::Swing => 0.1,
+            Sound::HitEnemy => 0.15,
+            Sound::HitPlayer => 0.25,
+            Sound::Purchase => 0.2,
+        }
+    }
+    fn source<'a>(&self, main_assets: &'a MainAssets) -> &'a SourceHandle {
+        match self {
+            Sound::Swing => &main_assets.swing_sound,
+            Sound::HitEnemy => &main_assets.player_hit_sound,
+            Sound::HitPlayer => &main_assets.enemy_hit_sound,
+            Sound::Purchase => &main_assets.purchase_sound,
+        }
+    }
+}
+
+pub struct SoundSystem {
+    reader: Option<ReaderId<Sound>>,
+    animation_reader: Option<ReaderId<AnimationEvent>>,
+    cooldowns: HashMap<Sound, f32>,
+}
+impl SoundSystem {
+    pub fn new() -> SoundSystem {
+        SoundSystem {
+            reader: None,
+            animation_reader: None,
+            cooldowns: HashMap::new(),
+        }
+    }
+}
+impl<'s> System<'s> for SoundSystem {
+    type SystemData = (
+        Read<'s, EventChannel<Sound>>,
+        Read<'s, EventChannel<AnimationEvent>>,
+        ReadExpect<'s, MainAssets>,
+        Read<'s, AssetStorage<Source>>,
+        Option<Read<'s, Output>>,
+        Read<'s, Time>,
+    );
+    fn run(&mut self, (sounds, animation_events, main_assets, sources, output, time) : Self::SystemData) {
+        for cooldown in self.cooldowns.values_mut() {
+            *cooldown -= time.delta_seconds();
+        }
+        let mut played = Vec::new();
+        played.extend(sounds.read(self.reader.as_mut().unwrap()).cloned());
+        for animation_event in animation_events.read(self.animation_reader.as_mut().unwrap()) {
+            if let FrameEvent::Sound(sound) = animation_event.event {
+                played.push(sound);
+            }
+        }
+        for sound in played.iter() {
+            if let Some(cooldown) = self.cooldowns.get(sound) {
+                if *cooldown > 0.0 {
+                    continue;
+                }
+            }
+            self.cooldowns.insert(*sound, sound.cooldown());
+            if let (Some(output), Some(source)) = (output.as_ref(), sources.get(sound.source(&main_assets))) {
+                output.play_once(source, 1.0);
+            }
+        }
+    }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.reader = Some(res.fetch_mut::<EventChannel<Sound>>().register_reader());
+        self.animation_reader = Some(res.fetch_mut::<EventChannel<AnimationEvent>>().register_reader());
+    }
+}
@@ -53,3 +53,5 @@ pub const BUY_UPGRADES: usize = 53;
 
 pub const PORTAL_CLOSED: usize = 54;
 pub const PORTAL_SPIN: [usize; 7] = [55, 56, 57, 58, 59, 60, 61];
+
+pub const ARROW: usize = 66;
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use amethyst::{
+    prelude::*,
+    renderer::SpriteSheetHandle,
+};
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+use crate::boss::{spawn_boss, Bosses};
+use crate::drops::*;
+use crate::enemies::{spawn_enemy, EnemyArchetypes};
+use crate::world::*;
+
+const FLIP_FLAGS: u32 = 0xE000_0000;
+
+#[derive(Debug, Clone, Default)]
+pub struct TileProperties {
+    pub sprite: Option<usize>,
+    pub solid: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub tiles: BTreeMap<u32, TileProperties>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TiledObject {
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub tile: bool,
+    pub properties: BTreeMap<String, String>,
+}
+impl TiledObject {
+    pub fn property<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.properties.get(name).and_then(|value| value.parse().ok())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TiledMap {
+    pub width: i32,
+    pub height: i32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<Vec<u32>>,
+    pub objects: Vec<TiledObject>,
+}
+impl TiledMap {
+    pub fn load(path: &str) -> Result<TiledMap, String> {
+        let mut parser = Parser::new(path, None);
+        parser.parse_file(path)?;
+        let map = parser.map;
+        for layer in map.layers.iter() {
+            for (index, gid) in layer.iter().enumerate() {
+                if *gid & !FLIP_FLAGS != 0 && map.tile(*gid).is_none() {
+                    let (x, y) = map.tile_position(index);
+                    return Err(format!("{}: tile {} at ({}, {}) has no sprite or solid property in its tileset", path, gid & !FLIP_FLAGS, x, y));
+                }
+            }
+        }
+        Ok(map)
+    }
+    pub fn tile(&self, gid: u32) -> Option<TileProperties> {
+        let gid = gid & !FLIP_FLAGS;
+        if gid == 0 {
+            return None;
+        }
+        let tileset = self.tilesets.iter()
+            .filter(|tileset| tileset.first_gid <= gid)
+            .max_by_key(|tileset| tileset.first_gid)?;
+        tileset.tiles.get(&(gid - tileset.first_gid)).cloned()
+    }
+    pub fn tile_position(&self, index: usize) -> (i32, i32) {
+        let column = index as i32 % self.width;
+        let row = index as i32 / self.width;
+        (column, self.height - 1 - row)
+    }
+    pub fn world_position(&self, object: &TiledObject) -> (f32, f32) {
+        let center_x = object.x + object.width / 2.0;
+        let center_y = if object.tile {
+            object.y - object.height / 2.0
+        } else {
+            object.y + object.height / 2.0
+        };
+        let scale = (TILE_SIZE.0 as f32 / self.tile_width, TILE_SIZE.1 as f32 / self.tile_height);
+        let origin = (
+            WORLD_BASE.0 as f32 - TILE_SIZE.0 as f32 / 2.0,
+            WORLD_BASE.1 as f32 - TILE_SIZE.1 as f32 / 2.0,
+        );
+        (
+            origin.0 + center_x * scale.0,
+            origin.1 + (self.height as f32 * self.tile_height - center_y) * scale.1,
+        )
+    }
+}
+
+fn attribute(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes.iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.clone())
+}
+fn parsed<T: std::str::FromStr>(attributes: &[OwnedAttribute], name: &str) -> Option<T> {
+    attribute(attributes, name).and_then(|value| value.parse().ok())
+}
+
+struct Parser {
+    map: TiledMap,
+    directory: String,
+    external_first_gid: Option<u32>,
+    tileset: Option<Tileset>,
+    tile: Option<u32>,
+    layer: Option<Vec<u32>>,
+    in_data: bool,
+    object: Option<TiledObject>,
+}
+impl Parser {
+    fn new(path: &str, external_first_gid: Option<u32>) -> Parser {
+        let directory = Path::new(path).parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Parser {
+            map: TiledMap::default(),
+            directory,
+            external_first_gid,
+            tileset: None,
+            tile: None,
+            layer: None,
+            in_data: false,
+            object: None,
+        }
+    }
+    fn parse_file(&mut self, path: &str) -> Result<(), String> {
+        let file = File::open(path).map_err(|err| format!("Couldn't open {}: {}", path, err))?;
+        for event in EventReader::new(BufReader::new(file)) {
+            let event = event.map_err(|err| format!("Couldn't parse {}: {}", path, err))?;
+            self.event(event).map_err(|err| format!("{}: {}", path, err))?;
+        }
+        Ok(())
+    }
+    fn event(&mut self, event: XmlEvent) -> Result<(), String> {
+        match event {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                self.start(&name.local_name, &attributes)
+            },
+            XmlEvent::EndElement { name } => {
+                self.end(&name.local_name);
+                Ok(())
+            },
+            XmlEvent::Characters(text) => {
+                if self.in_data {
+                    let layer = self.layer.as_mut().ok_or("Tile data outside of a layer")?;
+                    for value in text.split(',').map(str::trim).filter(|value| !value.is_empty()) {
+                        layer.push(value.parse().map_err(|_| format!("Bad tile id {}", value))?);
+                    }
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+    fn start(&mut self, name: &str, attributes: &[OwnedAttribute]) -> Result<(), String> {
+        match name {
+            "map" => {
+                self.map.width = parsed(attributes, "width").ok_or("Map has no width")?;
+                self.map.height = parsed(attributes, "height").ok_or("Map has no height")?;
+                self.map.tile_width = parsed(attributes, "tilewidth").unwrap_or(TILE_SIZE.0 as f32);
+                self.map.tile_height = parsed(attributes, "tileheight").unwrap_or(TILE_SIZE.1 as f32);
+            },
+            "tileset" => {
+                let first_gid = parsed(attributes, "firstgid")
+                    .or(self.external_first_gid)
+                    .ok_or("Tileset has no firstgid")?;
+                if let Some(source) = attribute(attributes, "source") {
+                    let path = Path::new(&self.directory).join(source).to_string_lossy().into_owned();
+                    let mut parser = Parser::new(&path, Some(first_gid));
+                    parser.parse_file(&path)?;
+                    self.map.tilesets.extend(parser.map.tilesets);
+                } else {
+                    self.tileset = Some(Tileset { first_gid, tiles: BTreeMap::new() });
+                }
+            },
+            "tile" => {
+                if self.tileset.is_some() {
+                    self.tile = parsed(attributes, "id");
+                }
+            },
+            "layer" => {
+                self.layer = Some(Vec::new());
+            },
+            "data" => {
+                match attribute(attributes, "encoding") {
+                    Some(ref encoding) if encoding == "csv" => self.in_data = true,
+                    encoding => return Err(format!("Unsupported layer encoding {:?}, save the map as CSV", encoding)),
+                }
+            },
+            "object" => {
+                self.object = Some(TiledObject {
+                    kind: attribute(attributes, "type")
+                        .or_else(|| attribute(attributes, "class"))
+                        .unwrap_or_default(),
+                    x: parsed(attributes, "x").unwrap_or(0.0),
+                    y: parsed(attributes, "y").unwrap_or(0.0),
+                    width: parsed(attributes, "width").unwrap_or(0.0),
+                    height: parsed(attributes, "height").unwrap_or(0.0),
+                    tile: attribute(attributes, "gid").is_some(),
+                    properties: BTreeMap::new(),
+                });
+            },
+            "property" => {
+                let name = attribute(attributes, "name").ok_or("Property has no name")?;
+                let value = attribute(attributes, "value").unwrap_or_default();
+                if let Some(object) = self.object.as_mut() {
+                    object.properties.insert(name, value);
+                } else if let (Some(tileset), Some(id)) = (self.tileset.as_mut(), self.tile) {
+                    match name.as_str() {
+                        "sprite" => {
+                            let sprite = value.parse().map_err(|_| format!("Bad sprite {}", value))?;
+                            tileset.tiles.entry(id).or_default().sprite = Some(sprite);
+                        },
+                        "solid" => tileset.tiles.entry(id).or_default().solid = value == "true",
+                        _ => {},
+                    }
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+    fn end(&mut self, name: &str) {
+        match name {
+            "tileset" => {
+                if let Some(tileset) = self.tileset.take() {
+                    self.map.tilesets.push(tileset);
+                }
+            },
+            "tile" => self.tile = None,
+            "data" => self.in_data = false,
+            "layer" => {
+                if let Some(layer) = self.layer.take() {
+                    self.map.layers.push(layer);
+                }
+            },
+            "object" => {
+                if let Some(object) = self.object.take() {
+                    self.map.objects.push(object);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn upgrade_named(name: &str) -> Option<Upgrade> {
+    match name {
+        "HeartBracelet" => Some(Upgrade::HeartBracelet),
+        "GoldenAegis" => Some(Upgrade::GoldenAegis),
+        "CursedRing" => Some(Upgrade::CursedRing),
+        _ => None,
+    }
+}
+
+fn check_objects(world: &World, map: &TiledMap) -> Result<(), String> {
+    let archetypes = world.read_resource::<EnemyArchetypes>();
+    let bosses = world.read_resource::<Bosses>();
+    for object in map.objects.iter() {
+        match object.kind.as_str() {
+            "player_start" | "portal" => {},
+            "chest" => {
+                if let Some(name) = object.properties.get("upgrade") {
+                    if upgrade_named(name).is_none() {
+                        return Err(format!("Chest at ({}, {}) has unknown upgrade {:?}", object.x, object.y, name));
+                    }
+                }
+            },
+            kind => {
+                if archetypes.get(kind).is_none() && !bosses.bosses.contains_key(kind) {
+                    return Err(format!("Unknown map object type {:?} at ({}, {})", kind, object.x, object.y));
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+pub fn build_map(world: &mut World, sprite_sheet: &SpriteSheetHandle, map: &TiledMap) -> Result<Option<(f32, f32)>, String> {
+    check_objects(world, map)?;
+    init_world(world, (map.width, map.height));
+    for layer in map.layers.iter() {
+        for (index, gid) in layer.iter().enumerate() {
+            if let Some(tile) = map.tile(*gid) {
+                let (x, y) = map.tile_position(index);
+                if let Some(sprite) = tile.sprite {
+                    spawn_world_tile(world, sprite_sheet.clone(), x, y, sprite).build();
+                }
+                if tile.solid {
+                    world.write_resource::<TileMap>().set_solid(x, y, true);
+                }
+            }
+        }
+    }
+    let mut player_start = None;
+    for object in map.objects.iter() {
+        let (x, y) = map.world_position(object);
+        match object.kind.as_str() {
+            "player_start" => {
+                player_start = Some((x, y));
+            },
+            "portal" => {
+                portal(world, x, y).build();
+            },
+            "chest" => {
+                let cost = object.property("cost").unwrap_or(1);
+                let upgrade = object.properties.get("upgrade")
+                    .and_then(|name| upgrade_named(name))
+                    .unwrap_or(Upgrade::HeartBracelet);
+                spawn_chest(world, x, y, cost, upgrade);
+            },
+            kind => {
+                if let Some(builder) = spawn_enemy(world, kind, x, y) {
+                    builder.build();
+                } else {
+                    spawn_boss(world, kind, x, y);
+                }
+            },
+        }
+    }
+    Ok(player_start)
+}
@@ -8,6 +8,8 @@ use amethyst::{
     audio::*,
 };
 use crate::basics::*;
+use crate::ai::*;
+use crate::simulation::Interpolation;
 use rand::prelude::*;
 use rand::distributions::Standard;
 pub use rand::seq::SliceRandom;
@@ -33,6 +35,24 @@ pub fn get_resource(str: &str) -> String {
     )
 }
 
+pub fn has_arg(name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    std::env::args().skip(1).any(|arg| arg == name || arg.starts_with(&prefix))
+}
+
+pub fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        } else if arg.starts_with(&prefix) {
+            return Some(arg[prefix.len()..].to_string());
+        }
+    }
+    None
+}
+
 pub fn at(x: f32, y: f32) -> Transform {
     let mut transform = Transform::default();
     transform.set_x(x);
@@ -65,7 +85,8 @@ impl <'s> BuilderHelp for EntityBuilder<'s> {
         self
             .with(Velocity { vx: 0.0, vy: 0.0 })
             .with(Physical::new(size))
-            .with(Rotation::East)
+            .with(Rotation::EAST)
+            .with(Interpolation::new())
     }
     fn with_static(self, size: f32) -> EntityBuilder<'s> {
         self
@@ -116,6 +137,53 @@ pub fn load_sound<'a>(world: &mut World, path: String, progress: &'a mut Progres
         &source_store,
     )
 }
+pub fn load_animation<'a>(world: &mut World, path: String, progress: &'a mut ProgressCounter) -> AnimationHandle {
+    let loader = world.read_resource::<Loader>();
+    let animation_store = world.read_resource::<AssetStorage<HitboxAnimation>>();
+    loader.load(
+        path,
+        RonFormat,
+        (),
+        progress,
+        &animation_store,
+    )
+}
+pub fn get_animation(animations: &AssetStorage<HitboxAnimation>, handle: &AnimationHandle) -> HitboxAnimation {
+    animations.get(handle).cloned().unwrap_or_else(HitboxAnimation::new)
+}
+pub fn get_main_animation(world: &World, pick: fn(&MainAssets) -> &AnimationHandle) -> HitboxAnimation {
+    let main_assets = world.read_resource::<MainAssets>();
+    let animations = world.read_resource::<AssetStorage<HitboxAnimation>>();
+    get_animation(&animations, pick(&main_assets))
+}
+pub fn load_behavior<'a>(world: &mut World, path: String, progress: &'a mut ProgressCounter) -> BehaviorHandle {
+    let loader = world.read_resource::<Loader>();
+    let behavior_store = world.read_resource::<AssetStorage<BehaviorTree>>();
+    loader.load(
+        path,
+        RonFormat,
+        (),
+        progress,
+        &behavior_store,
+    )
+}
+pub fn get_behavior(behaviors: &AssetStorage<BehaviorTree>, handle: &BehaviorHandle) -> BehaviorTree {
+    behaviors.get(handle).cloned().unwrap_or_default()
+}
+pub fn get_named_animation(world: &World, name: &str) -> HitboxAnimation {
+    let main_assets = world.read_resource::<MainAssets>();
+    let animations = world.read_resource::<AssetStorage<HitboxAnimation>>();
+    main_assets.animations.get(name)
+        .map(|handle| get_animation(&animations, handle))
+        .unwrap_or_else(|| panic!("No animation named {}", name))
+}
+pub fn get_named_behavior(world: &World, name: &str) -> BehaviorTree {
+    let main_assets = world.read_resource::<MainAssets>();
+    let behaviors = world.read_resource::<AssetStorage<BehaviorTree>>();
+    main_assets.behaviors.get(name)
+        .map(|handle| get_behavior(&behaviors, handle))
+        .unwrap_or_else(|| panic!("No behavior named {}", name))
+}
 pub fn get_sprite_sheet(world: &World) -> SpriteSheetHandle {
     let main_sprite = world.read_resource::<MainAssets>();
     main_sprite.sprite_sheet.clone()
@@ -138,9 +206,6 @@ pub fn walking_animation(stand: usize, left: usize, right: usize, duration: f32)
     walking.set_sprite(frame, right);
     walking
 }
-pub fn random_between(low: f32, high: f32) -> f32 {
-    low + (high - low) * thread_rng().gen::<f32>()
-}
-pub fn rng() -> ThreadRng {
-    thread_rng()
+pub fn random_between<R: Rng>(rng: &mut R, low: f32, high: f32) -> f32 {
+    low + (high - low) * rng.gen::<f32>()
 }
@@ -5,33 +5,151 @@ use amethyst::{
     core::*,
     input::*,
     core::transform::*,
+    assets::AssetStorage,
+    shrev::{EventChannel, ReaderId},
 };
 use crate::sprites::*;
 use crate::basics::*;
 use crate::enemies::*;
+use crate::boss::Boss;
 use crate::utils::*;
+use crate::broadphase::*;
+use crate::replay::PlayerInput;
 
 pub struct WorldState {
     enemies_alive: usize,
+    bosses_alive: usize,
+    boss_floor: bool,
     on_portal: bool,
     opened_portal: bool,
 }
 impl WorldState {
+    fn cleared(&self) -> bool {
+        if self.boss_floor {
+            self.bosses_alive == 0
+        } else {
+            self.enemies_alive == 0
+        }
+    }
     pub fn new() -> WorldState {
         WorldState {
             enemies_alive: 1,
+            bosses_alive: 0,
+            boss_floor: false,
             on_portal: false,
             opened_portal: false,
         }
     }
 }
 
-const WORLD_BASE: (i32, i32) = (-256, -256);
-const TILE_SIZE: (i32, i32) = (16, 16);
+pub const WORLD_BASE: (i32, i32) = (-256, -256);
+pub const TILE_SIZE: (i32, i32) = (16, 16);
+
+#[derive(Debug, Default)]
+pub struct TileMap {
+    width: i32,
+    height: i32,
+    solid: Vec<bool>,
+}
+impl TileMap {
+    pub fn new(width: i32, height: i32) -> TileMap {
+        TileMap {
+            width,
+            height,
+            solid: vec![false; (width.max(0) * height.max(0)) as usize],
+        }
+    }
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+    pub fn set_solid(&mut self, x: i32, y: i32, solid: bool) {
+        if let Some(index) = self.index(x, y) {
+            self.solid[index] = solid;
+        }
+    }
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        match self.index(x, y) {
+            Some(index) => self.solid[index],
+            None => false,
+        }
+    }
+    pub fn tile_center(x: i32, y: i32) -> (f32, f32) {
+        ((WORLD_BASE.0 + x * TILE_SIZE.0) as f32, (WORLD_BASE.1 + y * TILE_SIZE.1) as f32)
+    }
+    pub fn tile_at((wx, wy): (f32, f32)) -> (i32, i32) {
+        (
+            ((wx - WORLD_BASE.0 as f32 + TILE_SIZE.0 as f32 / 2.0) / TILE_SIZE.0 as f32).floor() as i32,
+            ((wy - WORLD_BASE.1 as f32 + TILE_SIZE.1 as f32 / 2.0) / TILE_SIZE.1 as f32).floor() as i32,
+        )
+    }
+    pub fn tile_bounds(x: i32, y: i32) -> Bounds {
+        let (cx, cy) = TileMap::tile_center(x, y);
+        let (hw, hh) = (TILE_SIZE.0 as f32 / 2.0, TILE_SIZE.1 as f32 / 2.0);
+        (cx - hw, cy - hh, cx + hw, cy + hh)
+    }
+    pub fn solid_tiles_in(&self, (x0, y0, x1, y1): Bounds) -> Vec<(i32, i32)> {
+        let (tx0, ty0) = TileMap::tile_at((x0, y0));
+        let (tx1, ty1) = TileMap::tile_at((x1, y1));
+        let mut tiles = Vec::new();
+        for x in tx0.max(0)..=tx1.min(self.width - 1) {
+            for y in ty0.max(0)..=ty1.min(self.height - 1) {
+                if self.is_solid(x, y) {
+                    tiles.push((x, y));
+                }
+            }
+        }
+        tiles
+    }
+    pub fn first_solid_between(&self, from: (f32, f32), to: (f32, f32)) -> Option<(i32, i32)> {
+        let (mut x, mut y) = TileMap::tile_at(from);
+        let (end_x, end_y) = TileMap::tile_at(to);
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let (x0, y0, x1, y1) = TileMap::tile_bounds(x, y);
+        let (step_x, mut next_x, delta_x) = if dx > 0.0 {
+            (1, (x1 - from.0) / dx, TILE_SIZE.0 as f32 / dx)
+        } else if dx < 0.0 {
+            (-1, (x0 - from.0) / dx, -TILE_SIZE.0 as f32 / dx)
+        } else {
+            (0, std::f32::INFINITY, std::f32::INFINITY)
+        };
+        let (step_y, mut next_y, delta_y) = if dy > 0.0 {
+            (1, (y1 - from.1) / dy, TILE_SIZE.1 as f32 / dy)
+        } else if dy < 0.0 {
+            (-1, (y0 - from.1) / dy, -TILE_SIZE.1 as f32 / dy)
+        } else {
+            (0, std::f32::INFINITY, std::f32::INFINITY)
+        };
+        loop {
+            if self.is_solid(x, y) {
+                return Some((x, y));
+            }
+            if (x == end_x && y == end_y) || next_x.min(next_y) > 1.0 {
+                return None;
+            }
+            if next_x < next_y {
+                x += step_x;
+                next_x += delta_x;
+            } else {
+                y += step_y;
+                next_y += delta_y;
+            }
+        }
+    }
+}
 
 pub fn spawn_world_tile(world: &mut World, sprite_sheet: SpriteSheetHandle, x: i32, y: i32, sprite_number: usize) -> EntityBuilder {
-    let (tx, ty) = (WORLD_BASE.0 + x * TILE_SIZE.0, WORLD_BASE.1 + y * TILE_SIZE.1);
-    spawn_at_z(world, tx as f32, ty as f32, -1.0)
+    let (tx, ty) = TileMap::tile_center(x, y);
+    spawn_at_z(world, tx, ty, -1.0)
         .with_sprite(sprite_sheet.clone(), sprite_number)
 }
 
@@ -48,13 +166,9 @@ pub fn draw_wall(world: &mut World, sprite_sheet: &SpriteSheetHandle, (x, y): (i
         for y in y..(y + height) {
             spawn_world_tile(world, sprite_sheet.clone(), x, y, sprite_number)
                 .build();
+            world.write_resource::<TileMap>().set_solid(x, y, true);
         }
     }
-    let (tx, ty) = (WORLD_BASE.0 + x * TILE_SIZE.0, WORLD_BASE.1 + y * TILE_SIZE.1);
-    let (w, h) = (TILE_SIZE.0 * width, TILE_SIZE.1 * height);
-    spawn_at_z(world, (tx + w / 2 - TILE_SIZE.0 / 2) as f32, (ty + h / 2 - TILE_SIZE.1 / 2) as f32, -1.0)
-        .with(Physical::new_wall(w as f32, h as f32))
-        .build();
 }
 #[derive(Debug, Component, Default)]
 #[storage(NullStorage)]
@@ -67,62 +181,72 @@ impl<'s> System<'s> for PortalSystem {
         WriteStorage<'s, AnimationController>,
         ReadStorage<'s, Portal>,
         ReadStorage<'s, Enemy>,
+        ReadStorage<'s, Boss>,
+        ReadExpect<'s, MainAssets>,
+        Read<'s, AssetStorage<HitboxAnimation>>,
     );
-    fn run(&mut self, (mut world_state, mut animation, portal, enemies) : Self::SystemData) {
+    fn run(&mut self, (mut world_state, mut animation, portal, enemies, bosses, main_assets, animations) : Self::SystemData) {
         if let Some(world_state) = world_state.as_mut() {
             world_state.enemies_alive = 0;
             for (enemy) in (enemies).join() {
                 world_state.enemies_alive += 1;
             }
+            world_state.bosses_alive = bosses.join().count();
+            world_state.boss_floor |= world_state.bosses_alive > 0;
             println!("{}", world_state.enemies_alive);
-            if world_state.enemies_alive == 0 && !world_state.opened_portal {
+            if world_state.cleared() && !world_state.opened_portal {
                 for (animation_controller, portal) in (&mut animation, &portal).join() {
-                    animation_controller.start_loop(portal_animation(0.15));
+                    animation_controller.start_loop(get_animation(&animations, &main_assets.portal_spin));
                     world_state.opened_portal = true;
                 }
             }
         }
     }
 }
-pub struct ExitSystem;
+pub struct ExitSystem {
+    contacts: Option<ReaderId<ContactEvent>>,
+}
+impl ExitSystem {
+    pub fn new() -> ExitSystem {
+        ExitSystem { contacts: None }
+    }
+}
 impl<'s> System<'s> for ExitSystem {
     type SystemData = (
-        ReadStorage<'s, HitState>,
-        WriteStorage<'s, Transform>,
-        Entities<'s>,
+        Read<'s, EventChannel<ContactEvent>>,
+        ReadStorage<'s, Transform>,
         <Self as HitboxCollisionSystem<'s>>::ExtraData,
     );
-    fn run(&mut self, mut system_data: Self::SystemData) {
-        let extra = &mut system_data.3;
-        let world_state = &mut extra.0;
-        if let Some(mut world_state) = world_state.as_mut() {
-            world_state.on_portal = false;
-        }
+    fn run(&mut self, system_data: Self::SystemData) {
         self.check_collisions(system_data);
     }
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.setup_contacts(res);
+    }
 }
 impl<'s> HitboxCollisionSystem<'s> for ExitSystem {
     type ExtraData = (
         Write<'s, Option<WorldState>>,
     );
-    fn collide(&self, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &WriteStorage<'s, Transform>, extra: &mut Self::ExtraData) {
+    fn collide(&self, phase: ContactPhase, collision: HitboxCollision, entity_a: Entity, entity_b: Entity, transforms: &ReadStorage<'s, Transform>, extra: &mut Self::ExtraData) {
         let world_state = &mut extra.0;
         if let Some(mut world_state) = world_state.as_mut() {
-            world_state.on_portal = true;
+            world_state.on_portal = phase != ContactPhase::Exit;
         }
     }
-    fn source() -> usize {
-        PORTAL_BOX
+    fn contacts(&mut self) -> &mut Option<ReaderId<ContactEvent>> {
+        &mut self.contacts
     }
-    fn target() -> usize {
-        PLAYER_INTERACT_BOX
+    fn interaction() -> Interaction {
+        Interaction::Exit
     }
 }
 
-pub fn want_advance<'s>((world_state, input): (Read<'s, Option<WorldState>>, Read<'s, InputHandler<String, String>>)) -> bool {
+pub fn want_advance<'s>((world_state, input): (Read<'s, Option<WorldState>>, Read<'s, PlayerInput>)) -> bool {
     if let Some(world_state) = world_state.as_ref() {
-        if let Some(true) = input.action_is_down("interact") {
-            world_state.enemies_alive == 0 && world_state.on_portal
+        if input.interact {
+            world_state.cleared() && world_state.on_portal
         } else {
             false
         }
@@ -131,57 +255,16 @@ pub fn want_advance<'s>((world_state, input): (Read<'s, Option<WorldState>>, Rea
     }
 }
 
-pub fn init_world(world: &mut World) {
+pub fn init_world(world: &mut World, (width, height): (i32, i32)) {
     world.add_resource(Some(WorldState::new()));
+    world.add_resource(TileMap::new(width, height));
 }
 
 
-fn portal_animation(speed: f32) -> HitboxAnimation {
-    let mut animation = HitboxAnimation::new();
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[0]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[1]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[2]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[3]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[4]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[5]);
-    let frame = animation.add_frame(speed);
-    animation.set_sprite(frame, PORTAL_SPIN[6]);
-    animation
-}
-
-fn heart_spin_animation(rotation_speed: f32, pause_duration: f32, heart_spin: [usize; 8]) -> HitboxAnimation {
-    let mut animation = HitboxAnimation::new();
-    let frame = animation.add_frame(pause_duration);
-    animation.set_sprite(frame, heart_spin[0]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[1]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[2]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[3]);
-    let frame = animation.add_frame(pause_duration);
-    animation.set_sprite(frame, heart_spin[4]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[5]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[6]);
-    let frame = animation.add_frame(rotation_speed);
-    animation.set_sprite(frame, heart_spin[7]);
-    animation
-}
-
 pub fn heart_spin(world: &mut World, x: f32, y: f32) -> EntityBuilder {
     let sprite_sheet = get_sprite_sheet(world);
     let mut animation_controller = AnimationController::new();
-    let rotation_speed = 0.15;
-    let pause_duration = 0.5;
-    animation_controller.start_loop(heart_spin_animation(rotation_speed, pause_duration, HEART_SPIN));
+    animation_controller.start_loop(get_main_animation(world, |assets| &assets.heart_spin));
     spawn_at(world, x, y)
         .with_sprite(sprite_sheet, HEART_SPIN[0])
         .with(animation_controller)
@@ -190,9 +273,7 @@ pub fn heart_spin(world: &mut World, x: f32, y: f32) -> EntityBuilder {
 pub fn spend_heart_spin(world: &mut World, x: f32, y: f32) -> EntityBuilder {
     let sprite_sheet = get_sprite_sheet(world);
     let mut animation_controller = AnimationController::new();
-    let rotation_speed = 0.25;
-    let pause_duration = 0.25;
-    animation_controller.start_loop(heart_spin_animation(rotation_speed, pause_duration, SPEND_HEART_SPIN));
+    animation_controller.start_loop(get_main_animation(world, |assets| &assets.spend_heart_spin));
     spawn_at(world, x, y)
         .with_sprite(sprite_sheet, SPEND_HEART_SPIN[0])
         .with(animation_controller)
@@ -202,7 +283,7 @@ pub fn portal(world: &mut World, x: f32, y: f32) -> EntityBuilder {
     let sprite_sheet = get_sprite_sheet(world);
     let mut animation_controller = AnimationController::new();
     let mut hitstate = HitState::new();
-    hitstate.set(PORTAL_BOX, 8.0, 8.0, (0.0, 0.0));
+    hitstate.set(world.read_resource::<CollisionLayers>().named("portal"), 8.0, 8.0, (0.0, 0.0));
     spawn_at(world, x, y)
         .with_sprite(sprite_sheet, PORTAL_CLOSED)
         .with(Portal)
@@ -0,0 +1,373 @@
+extern crate amethyst;
+extern crate ludumdare44;
+
+use amethyst::{
+    ecs::*,
+    core::transform::Transform,
+    renderer::SpriteRender,
+};
+use ludumdare44::ai::{Behavior, BehaviorTree, Brain};
+use ludumdare44::basics::{stage, AnimationFrameData, CollisionLayerConfig, CollisionLayers, FrameHitboxData, HitboxAnimationData, HitboxShape, HitState, Interaction, LayerPairConfig};
+use ludumdare44::boss::{spawn_boss, Boss, Bosses};
+use ludumdare44::combat::Health;
+use ludumdare44::dungeon::{draw_dungeon, generate, populate_dungeon};
+use ludumdare44::drops::Chest;
+use ludumdare44::enemies::{spawn_enemy, spawn_goblin, ChaseAndWanderEnemy, Enemy, EnemyArchetypes};
+use ludumdare44::harness::Harness;
+use ludumdare44::random::GameRng;
+use ludumdare44::utils::{get_resource, get_sprite_sheet};
+use ludumdare44::player::{spawn_player, Player, PlayerState};
+use ludumdare44::replay::PlayerInput;
+use ludumdare44::physics::line_of_sight;
+use ludumdare44::projectiles::Projectile;
+use ludumdare44::tiled::{build_map, TiledMap};
+use ludumdare44::simulation::FixedStep;
+use ludumdare44::sprites::{ARROW, WALL};
+use ludumdare44::world::{draw_wall, init_world, portal, want_advance, TileMap, TILE_SIZE};
+
+fn with_player() -> Harness {
+    let mut harness = Harness::new(44);
+    spawn_player(&mut harness.world, &PlayerState::new(), (stage.0 / 2.0, stage.1 / 2.0));
+    harness
+}
+
+fn player_health(harness: &Harness) -> i32 {
+    let players = harness.world.read_storage::<Player>();
+    let health = harness.world.read_storage::<Health>();
+    (&players, &health).join().map(|(_, health)| health.left).next().unwrap()
+}
+
+fn player_position(harness: &Harness) -> (f32, f32) {
+    let players = harness.world.read_storage::<Player>();
+    let transforms = harness.world.read_storage::<Transform>();
+    (&players, &transforms).join().map(|(_, transform)| (transform.translation().x, transform.translation().y)).next().unwrap()
+}
+
+fn enemy_positions(harness: &Harness) -> Vec<(f32, f32)> {
+    let enemies = harness.world.read_storage::<Enemy>();
+    let transforms = harness.world.read_storage::<Transform>();
+    (&enemies, &transforms).join().map(|(_, transform)| (transform.translation().x, transform.translation().y)).collect()
+}
+
+#[test]
+fn goblin_damages_idle_player() {
+    let mut harness = with_player();
+    spawn_goblin(&mut harness.world, stage.0 / 2.0 + 16.0, stage.1 / 2.0).build();
+    harness.run_seconds(6.0, PlayerInput::default());
+    assert!(player_health(&harness) < PlayerState::new().health);
+}
+
+#[test]
+fn player_attack_kills_goblin() {
+    let mut harness = with_player();
+    spawn_goblin(&mut harness.world, stage.0 / 2.0 + 16.0, stage.1 / 2.0).build();
+    let attack = PlayerInput { attack: true, ..PlayerInput::default() };
+    for _ in 0..600 {
+        harness.step(attack);
+        if enemy_positions(&harness).is_empty() {
+            break;
+        }
+    }
+    assert!(enemy_positions(&harness).is_empty());
+    assert!(player_health(&harness) > 0);
+}
+
+#[test]
+fn attack_follow_through_is_not_cut_short() {
+    let mut harness = with_player();
+    harness.step(PlayerInput { attack: true, ..PlayerInput::default() });
+    let attack_layer = harness.world.read_resource::<CollisionLayers>().named("player_attack");
+    let mut follow_through = 0;
+    for _ in 0..60 {
+        harness.step(PlayerInput::default());
+        let players = harness.world.read_storage::<Player>();
+        let hitstates = harness.world.read_storage::<HitState>();
+        for (_, hitstate) in (&players, &hitstates).join() {
+            if hitstate.get(attack_layer).map(|hitbox| hitbox.width == 8.0).unwrap_or(false) {
+                follow_through += 1;
+            }
+        }
+    }
+    let tick = harness.world.read_resource::<FixedStep>().delta_seconds();
+    assert!(follow_through as f32 * tick >= 0.2 - tick, "follow through lasted {} ticks", follow_through);
+}
+
+#[test]
+fn walls_stop_fast_walking() {
+    let mut harness = with_player();
+    let sprite_sheet = get_sprite_sheet(&harness.world);
+    draw_wall(&mut harness.world, &sprite_sheet, (0, 0), (2, 32), WALL);
+    {
+        let mut players = harness.world.write_storage::<Player>();
+        for player in (&mut players).join() {
+            player.walk_accel = 6000.0;
+            player.walk_speed = 1500.0;
+        }
+    }
+    let tick = harness.world.read_resource::<FixedStep>().delta_seconds();
+    assert!(1500.0 * tick > TILE_SIZE.0 as f32);
+    let (start, _) = player_position(&harness);
+    harness.run_seconds(2.0, PlayerInput { leftright: -1.0, ..PlayerInput::default() });
+    let (x, _) = player_position(&harness);
+    let wall_edge = TileMap::tile_center(1, 0).0 + TILE_SIZE.0 as f32 / 2.0;
+    assert!(x < start - 100.0, "player didn't walk left from {}", start);
+    assert!(x >= wall_edge, "player tunneled through the wall to {}", x);
+}
+
+#[test]
+fn collision_layers_come_only_from_config() {
+    let harness = Harness::new(44);
+    let layers = harness.world.read_resource::<CollisionLayers>();
+    assert!(layers.layer("player_attack").is_some());
+    let config = CollisionLayerConfig {
+        layers: vec!["arrow".to_string(), "target".to_string()],
+        pairs: vec![LayerPairConfig { source: "arrow".to_string(), target: "target".to_string(), interaction: Interaction::EnemyDamage }],
+    };
+    let custom = CollisionLayers::from_config(&config).unwrap();
+    assert_eq!(custom.layer("player_attack"), None);
+    assert_eq!(custom.pairs().len(), 1);
+    let typo = CollisionLayerConfig {
+        layers: vec!["arrow".to_string()],
+        pairs: vec![LayerPairConfig { source: "arrow".to_string(), target: "targt".to_string(), interaction: Interaction::EnemyDamage }],
+    };
+    assert!(CollisionLayers::from_config(&typo).is_err());
+    let animation = HitboxAnimationData {
+        frames: vec![AnimationFrameData {
+            duration: 0.1,
+            velocity: None,
+            sprite: None,
+            hitboxes: vec![FrameHitboxData {
+                layer: "player_atack".to_string(),
+                width: 4.0,
+                height: 4.0,
+                offset: (0.0, 0.0),
+                shape: HitboxShape::default(),
+            }],
+            events: Vec::new(),
+        }],
+        cancel: Vec::new(),
+    };
+    assert!(animation.build(&layers).is_err());
+}
+
+#[test]
+fn portal_advances_once_enemies_are_dead() {
+    let mut harness = with_player();
+    portal(&mut harness.world, stage.0 / 2.0, stage.1 / 2.0).build();
+    harness.run(5, PlayerInput { interact: true, ..PlayerInput::default() });
+    assert!(harness.world.exec(want_advance));
+}
+
+#[test]
+fn same_seed_wanders_the_same_way() {
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let mut harness = Harness::new(7);
+        spawn_goblin(&mut harness.world, 0.0, 0.0).build();
+        harness.run_seconds(8.0, PlayerInput::default());
+        runs.push(enemy_positions(&harness));
+    }
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[test]
+fn walls_block_line_of_sight() {
+    let mut tile_map = TileMap::new(32, 32);
+    tile_map.set_solid(21, 21, true);
+    let (cx, cy) = TileMap::tile_center(21, 21);
+    assert!(!line_of_sight(&tile_map, &[], (cx - 20.0, cy), (cx + 20.0, cy)));
+    assert!(line_of_sight(&tile_map, &[], (cx - 20.0, cy + 20.0), (cx + 20.0, cy + 20.0)));
+    let pillar = (cx + 30.0, cy - 50.0, cx + 34.0, cy + 50.0);
+    assert!(!line_of_sight(&tile_map, &[pillar], (cx + 20.0, cy), (cx + 40.0, cy)));
+}
+
+#[test]
+fn goblin_forgets_unseen_player() {
+    let mut harness = Harness::new(44);
+    let goblin = spawn_goblin(&mut harness.world, 0.0, 0.0).build();
+    harness.world.write_storage::<ChaseAndWanderEnemy>().get_mut(goblin).unwrap().notice((64.0, 0.0));
+    harness.run_seconds(1.0, PlayerInput::default());
+    assert!(harness.world.read_storage::<ChaseAndWanderEnemy>().get(goblin).unwrap().is_tracking());
+    harness.run_seconds(12.0, PlayerInput::default());
+    assert_eq!(harness.world.read_storage::<ChaseAndWanderEnemy>().get(goblin).unwrap().last_known_position(), None);
+}
+
+#[test]
+fn behavior_tree_drives_goblin() {
+    let mut harness = Harness::new(44);
+    let goblin = spawn_goblin(&mut harness.world, 0.0, 0.0).build();
+    let tree = BehaviorTree {
+        root: Behavior::Selector(vec![
+            Behavior::Sequence(vec![Behavior::SeesPlayer, Behavior::Flee]),
+            Behavior::Idle,
+        ]),
+    };
+    harness.world.write_storage::<Brain>().insert(goblin, Brain::new(tree)).unwrap();
+    harness.run_seconds(1.0, PlayerInput::default());
+    assert_eq!(enemy_positions(&harness), vec![(0.0, 0.0)]);
+    harness.world.write_storage::<ChaseAndWanderEnemy>().get_mut(goblin).unwrap().notice((64.0, 0.0));
+    harness.run_seconds(1.0, PlayerInput::default());
+    assert!(enemy_positions(&harness)[0].0 < -32.0);
+}
+
+#[test]
+fn goblin_paths_around_walls() {
+    let mut harness = Harness::new(44);
+    for y in 10..23 {
+        harness.world.write_resource::<TileMap>().set_solid(18, y, true);
+    }
+    let (sx, sy) = TileMap::tile_center(16, 16);
+    let target = TileMap::tile_center(20, 16);
+    let goblin = spawn_goblin(&mut harness.world, sx, sy).build();
+    for _ in 0..600 {
+        harness.world.write_storage::<ChaseAndWanderEnemy>().get_mut(goblin).unwrap().notice(target);
+        harness.step(PlayerInput::default());
+    }
+    let (gx, gy) = enemy_positions(&harness)[0];
+    assert!((gx - target.0).hypot(gy - target.1) < 20.0);
+}
+
+#[test]
+fn goblin_replans_after_failed_search() {
+    let mut harness = Harness::new(44);
+    for y in 0..32 {
+        harness.world.write_resource::<TileMap>().set_solid(18, y, true);
+    }
+    let (sx, sy) = TileMap::tile_center(16, 16);
+    let target = TileMap::tile_center(20, 16);
+    let goblin = spawn_goblin(&mut harness.world, sx, sy).build();
+    for _ in 0..60 {
+        harness.world.write_storage::<ChaseAndWanderEnemy>().get_mut(goblin).unwrap().notice(target);
+        harness.step(PlayerInput::default());
+    }
+    assert!(enemy_positions(&harness)[0].0 < TileMap::tile_center(17, 16).0);
+    harness.world.write_resource::<TileMap>().set_solid(18, 26, false);
+    for _ in 0..1200 {
+        harness.world.write_storage::<ChaseAndWanderEnemy>().get_mut(goblin).unwrap().notice(target);
+        harness.step(PlayerInput::default());
+    }
+    let (gx, gy) = enemy_positions(&harness)[0];
+    assert!((gx - target.0).hypot(gy - target.1) < 20.0);
+}
+
+#[test]
+fn archetypes_spawn_by_name() {
+    let mut harness = Harness::new(44);
+    let mut brute = harness.world.read_resource::<EnemyArchetypes>().get("goblin").cloned().unwrap();
+    brute.health = 5;
+    brute.melee = None;
+    harness.world.write_resource::<EnemyArchetypes>().archetypes.insert("brute".to_string(), brute);
+    assert!(spawn_enemy(&mut harness.world, "dragon", 0.0, 0.0).is_none());
+    let entity = spawn_enemy(&mut harness.world, "brute", 0.0, 0.0).unwrap().build();
+    assert_eq!(harness.world.read_storage::<Health>().get(entity).unwrap().left, 5);
+    assert!(harness.world.read_storage::<ChaseAndWanderEnemy>().get(entity).is_some());
+    let mut archetypes = harness.world.read_resource::<EnemyArchetypes>().clone();
+    archetypes.archetypes.get_mut("brute").unwrap().behavior = Some("gobiln".to_string());
+    let err = archetypes.validate(&harness.world.read_resource::<CollisionLayers>()).unwrap_err();
+    assert!(err.contains("brute") && err.contains("gobiln"), "{}", err);
+}
+
+#[test]
+fn archer_shoots_player_from_range() {
+    let mut harness = with_player();
+    spawn_enemy(&mut harness.world, "goblin_archer", stage.0 / 2.0 - 80.0, stage.1 / 2.0).unwrap().build();
+    let mut fired = false;
+    for _ in 0..300 {
+        harness.step(PlayerInput::default());
+        let projectiles = harness.world.read_storage::<Projectile>();
+        let sprites = harness.world.read_storage::<SpriteRender>();
+        for (_, sprite) in (&projectiles, &sprites).join() {
+            assert_eq!(sprite.sprite_number, ARROW);
+            fired = true;
+        }
+    }
+    assert!(fired);
+    assert!(player_health(&harness) < PlayerState::new().health);
+    let (ax, _) = enemy_positions(&harness)[0];
+    assert!(player_position(&harness).0 - ax > 32.0);
+}
+
+#[test]
+fn boss_phases_summon_adds_and_gate_the_portal() {
+    let mut harness = with_player();
+    assert_eq!(harness.world.read_resource::<Bosses>().for_level(3), Some("goblin_king"));
+    assert_eq!(harness.world.read_resource::<Bosses>().for_level(2), None);
+    portal(&mut harness.world, stage.0 / 2.0, stage.1 / 2.0).build();
+    let boss = spawn_boss(&mut harness.world, "goblin_king", 0.0, 0.0).unwrap();
+    let interact = PlayerInput { interact: true, ..PlayerInput::default() };
+    harness.step(interact);
+    assert_eq!(harness.world.read_storage::<Boss>().get(boss).unwrap().phase(), Some(0));
+    harness.world.write_storage::<Health>().get_mut(boss).unwrap().left = 7;
+    harness.run(2, interact);
+    assert_eq!(harness.world.read_storage::<Boss>().get(boss).unwrap().phase(), Some(1));
+    assert_eq!(enemy_positions(&harness).len(), 3);
+    assert!(!harness.world.exec(want_advance));
+    harness.world.write_storage::<Health>().get_mut(boss).unwrap().left = 0;
+    harness.run(2, interact);
+    assert_eq!(enemy_positions(&harness).len(), 2);
+    assert!(harness.world.exec(want_advance));
+}
+
+#[test]
+fn dungeon_floors_are_seeded() {
+    let mut layouts = Vec::new();
+    for _ in 0..2 {
+        let mut harness = Harness::new(44);
+        let layout = generate(harness.world.write_resource::<GameRng>().stream("dungeon"), 3);
+        init_world(&mut harness.world, (layout.width, layout.height));
+        let sprite_sheet = get_sprite_sheet(&harness.world);
+        draw_dungeon(&mut harness.world, &sprite_sheet, &layout);
+        populate_dungeon(&mut harness.world, &layout, None);
+        let (sx, sy) = layout.player_start;
+        assert!(!harness.world.read_resource::<TileMap>().is_solid(sx, sy));
+        harness.step(PlayerInput::default());
+        assert_eq!(enemy_positions(&harness).len(), layout.enemies.len());
+        layouts.push((layout.floor, layout.enemies, layout.portal));
+    }
+    assert_eq!(layouts[0], layouts[1]);
+    let first = generate(Harness::new(44).world.write_resource::<GameRng>().stream("dungeon"), 0);
+    let deep = generate(Harness::new(44).world.write_resource::<GameRng>().stream("dungeon"), 6);
+    assert!(first.rooms.len() <= 4);
+    assert!(deep.rooms.len() > first.rooms.len());
+    assert!(deep.enemies.len() > first.enemies.len());
+    assert!(deep.width > first.width);
+    assert_ne!(deep.boss, deep.portal);
+    assert!(deep.is_floor(deep.boss.0, deep.boss.1));
+}
+
+#[test]
+fn tiled_maps_build_floors() {
+    let mut harness = Harness::new(44);
+    let map = TiledMap::load(&get_resource("maps/tutorial.tmx")).unwrap();
+    let sprite_sheet = get_sprite_sheet(&harness.world);
+    let start = build_map(&mut harness.world, &sprite_sheet, &map).unwrap().expect("map has a player start");
+    {
+        let tile_map = harness.world.read_resource::<TileMap>();
+        assert!(tile_map.is_solid(0, 0));
+        assert!(tile_map.is_solid(8, 16));
+        assert!(!tile_map.is_solid(15, 16));
+        let (sx, sy) = TileMap::tile_at(start);
+        assert_eq!((sx, sy), (16, 6));
+    }
+    harness.step(PlayerInput::default());
+    assert_eq!(enemy_positions(&harness).len(), 2);
+    assert_eq!(harness.world.read_storage::<Chest>().join().count(), 2);
+
+    let mut broken = map.clone();
+    broken.objects.iter_mut().find(|object| object.kind == "chest").unwrap()
+        .properties.insert("upgrade".to_string(), "GoldenAegsi".to_string());
+    let err = build_map(&mut harness.world, &sprite_sheet, &broken).unwrap_err();
+    assert!(err.contains("GoldenAegsi"), "{}", err);
+    let mut broken = map.clone();
+    broken.objects.iter_mut().find(|object| object.kind == "chest").unwrap().kind = "chset".to_string();
+    let err = build_map(&mut harness.world, &sprite_sheet, &broken).unwrap_err();
+    assert!(err.contains("chset"), "{}", err);
+
+    let unmapped = std::env::temp_dir().join(format!("tiled_maps_build_floors_{}.tmx", std::process::id()));
+    std::fs::write(&unmapped, r#"<map width="1" height="1" tilewidth="16" tileheight="16">
+ <tileset firstgid="1" name="bare" tilewidth="16" tileheight="16"/>
+ <layer width="1" height="1"><data encoding="csv">5</data></layer>
+</map>"#).unwrap();
+    assert!(TiledMap::load(&unmapped.to_string_lossy()).is_err());
+    std::fs::remove_file(&unmapped).unwrap();
+}